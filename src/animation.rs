@@ -0,0 +1,336 @@
+use std::{f32::consts::PI, io};
+
+use crate::{
+    camera::{Camera, PerspectiveCamera},
+    image::{Image, RenderSettings},
+    rnd,
+    surfaces::Surfaces,
+    vec3::Vec3,
+};
+
+/// Camera pose at `time` seconds.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraKey {
+    pub time: f32,
+    pub lookfrom: Vec3,
+    pub lookat: Vec3,
+    pub vfov: f32,
+}
+
+/// Keyframed pinhole camera path, interpolated with a Catmull-Rom spline
+/// through the keys.
+#[derive(Clone, Debug)]
+pub struct CameraPath {
+    keys: Vec<CameraKey>,
+    vup: Vec3,
+    aspect: f32,
+    /// Length of one loop for paths ending where they start, see
+    /// `turntable`.
+    period: Option<f32>,
+}
+
+/// Number of keys per turn approximating the circle of a turntable.
+const N_TURNTABLE_KEYS: usize = 36;
+
+impl CameraPath {
+    pub fn new(keys: Vec<CameraKey>, vup: Vec3, aspect: f32) -> Self {
+        if keys.is_empty() {
+            panic!("keys must not be empty!");
+        }
+        if keys.windows(2).any(|pair| pair[0].time >= pair[1].time) {
+            panic!("keys must be sorted by time!");
+        }
+        Self {
+            keys,
+            vup,
+            aspect,
+            period: None,
+        }
+    }
+
+    /// Looping path orbiting `lookat` once every `period` seconds around the
+    /// `vup` axis, starting from `lookfrom`.
+    pub fn turntable(
+        lookfrom: Vec3,
+        lookat: Vec3,
+        vup: Vec3,
+        vfov: f32,
+        aspect: f32,
+        period: f32,
+    ) -> Self {
+        if period <= 0.0 {
+            panic!("period must be positive!");
+        }
+        let keys = (0..N_TURNTABLE_KEYS)
+            .map(|i| {
+                let fraction = i as f32 / N_TURNTABLE_KEYS as f32;
+                let offset = rotate(lookfrom - lookat, vup.get_unit(), 2.0 * PI * fraction);
+                CameraKey {
+                    time: fraction * period,
+                    lookfrom: lookat + offset,
+                    lookat,
+                    vfov,
+                }
+            })
+            .collect();
+        Self {
+            period: Some(period),
+            ..Self::new(keys, vup, aspect)
+        }
+    }
+
+    /// Time of the last key, or the loop period.
+    pub fn duration(&self) -> f32 {
+        self.period.unwrap_or(self.keys.last().unwrap().time)
+    }
+
+    /// Interpolated pose at `time`, clamped to the keys unless looping.
+    pub fn key_at(&self, time: f32) -> CameraKey {
+        let n_keys = self.keys.len() as i64;
+        // Key `i` of the (possibly unrolled) loop and its time.
+        let key = |i: i64| match self.period {
+            Some(period) => {
+                let key = self.keys[i.rem_euclid(n_keys) as usize];
+                (key, key.time + i.div_euclid(n_keys) as f32 * period)
+            }
+            None => {
+                let key = self.keys[i.clamp(0, n_keys - 1) as usize];
+                (key, key.time)
+            }
+        };
+        let time = match self.period {
+            Some(period) => time.rem_euclid(period),
+            None => time.clamp(self.keys[0].time, self.duration()),
+        };
+        let i = self.keys.partition_point(|key| key.time <= time) as i64 - 1;
+        let (keys, times): (Vec<_>, Vec<_>) = (i - 1..=i + 2).map(key).unzip();
+        if times[2] == times[1] {
+            return CameraKey { time, ..keys[1] };
+        }
+        let weights = catmull_rom_weights([times[0], times[1], times[2], times[3]], time);
+        let mut result = CameraKey {
+            time,
+            lookfrom: Vec3::zeros(),
+            lookat: Vec3::zeros(),
+            vfov: 0.0,
+        };
+        for (key, weight) in keys.iter().zip(weights) {
+            result.lookfrom += key.lookfrom.scale(weight);
+            result.lookat += key.lookat.scale(weight);
+            result.vfov += key.vfov * weight;
+        }
+        result
+    }
+
+    /// Pinhole camera at `time`.
+    pub fn camera_at(&self, time: f32) -> PerspectiveCamera {
+        let key = self.key_at(time);
+        let focus_dist = (key.lookfrom - key.lookat).length();
+        PerspectiveCamera::new(
+            key.lookfrom,
+            key.lookat,
+            self.vup,
+            key.vfov,
+            self.aspect,
+            0.0,
+            focus_dist,
+        )
+    }
+}
+
+/// Weights of the four control points `p0..p3` at `time` in the segment
+/// `p1..p2` of a Catmull-Rom spline with the control points at `times`.
+fn catmull_rom_weights(times: [f32; 4], time: f32) -> [f32; 4] {
+    let [t0, t1, t2, t3] = times;
+    let h = t2 - t1;
+    let s = (time - t1) / h;
+    let (s2, s3) = (s * s, s * s * s);
+    let h00 = 2.0 * s3 - 3.0 * s2 + 1.0;
+    let h10 = s3 - 2.0 * s2 + s;
+    let h01 = -2.0 * s3 + 3.0 * s2;
+    let h11 = s3 - s2;
+    // Tangents (p2 - p0) * a at p1 and (p3 - p1) * b at p2, scaled to the
+    // segment.
+    let a = if t2 > t0 { h / (t2 - t0) } else { 0.0 };
+    let b = if t3 > t1 { h / (t3 - t1) } else { 0.0 };
+    [-h10 * a, h00 - h11 * b, h01 + h10 * a, h11 * b]
+}
+
+/// Rotates `vec` by `angle` radians around the unit `axis`.
+fn rotate(vec: Vec3, axis: Vec3, angle: f32) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    vec.scale(cos) + axis.cross(&vec).scale(sin) + axis.scale(axis.dot(&vec) * (1.0 - cos))
+}
+
+/// Frames of an animation, each rendered to `n_passes` samples per pixel.
+#[derive(Clone, Copy, Debug)]
+pub struct Sequence {
+    pub n_frames: usize,
+    pub fps: f32,
+    pub n_passes: usize,
+    /// Seed the seeds of the frames are derived from.
+    pub seed: u64,
+}
+
+impl Default for Sequence {
+    fn default() -> Self {
+        Self {
+            n_frames: 48,
+            fps: 24.0,
+            n_passes: 16,
+            seed: 0,
+        }
+    }
+}
+
+impl Sequence {
+    pub fn frame_time(&self, frame: usize) -> f32 {
+        frame as f32 / self.fps
+    }
+
+    /// Renders the frames one after another with the camera `camera_at`
+    /// the frame time and hands each to `on_frame` with its number, e.g.
+    /// to save it with `frame_path`. Stops at the first error or once
+    /// `settings.cancel` is cancelled.
+    pub fn render<C, F, G>(
+        &self,
+        width: usize,
+        height: usize,
+        surfaces: &Surfaces,
+        settings: &RenderSettings,
+        camera_at: F,
+        mut on_frame: G,
+    ) -> io::Result<()>
+    where
+        C: Camera,
+        F: Fn(f32) -> C,
+        G: FnMut(usize, &Image) -> io::Result<()>,
+    {
+        for frame in 0..self.n_frames {
+            let camera = camera_at(self.frame_time(frame));
+            let seed = rnd::mix_seed(self.seed, frame as u64);
+            let mut image = Image::with_seed(width, height, seed);
+            image.render_progressive(&camera, surfaces, settings, self.n_passes, |_, _| true);
+            if settings.cancel.is_cancelled() {
+                break;
+            }
+            on_frame(frame, &image)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the run of `#` in `pattern` with the zero padded `frame`
+/// number, e.g. `frames/####.png` gives `frames/0007.png`. Without `#` the
+/// number is put before the extension.
+pub fn frame_path(pattern: &str, frame: usize) -> String {
+    match pattern.find('#') {
+        Some(start) => {
+            let width = pattern[start..].chars().take_while(|&c| c == '#').count();
+            format!(
+                "{}{:0width$}{}",
+                &pattern[..start],
+                frame,
+                &pattern[start + width..],
+                width = width
+            )
+        }
+        None => match pattern.rfind('.') {
+            Some(dot) => format!("{}{:04}{}", &pattern[..dot], frame, &pattern[dot..]),
+            None => format!("{}{:04}", pattern, frame),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::assert_close;
+
+    #[test]
+    fn test_camera_path() {
+        let key = |time, x: f32, vfov| CameraKey {
+            time,
+            lookfrom: Vec3::new(x, 1.0, 5.0),
+            lookat: Vec3::zeros(),
+            vfov,
+        };
+        let path = CameraPath::new(
+            vec![
+                key(0.0, 0.0, 30.0),
+                key(1.0, 1.0, 40.0),
+                key(3.0, 3.0, 60.0),
+            ],
+            Vec3::new(0.0, 1.0, 0.0),
+            1.5,
+        );
+        assert_eq!(path.duration(), 3.0);
+        // Passes through the keys and clamps outside of them.
+        assert_close(path.key_at(1.0).lookfrom, Vec3::new(1.0, 1.0, 5.0), 1e-3);
+        assert!((path.key_at(1.0).vfov - 40.0).abs() < 1e-4);
+        assert_close(path.key_at(-1.0).lookfrom, Vec3::new(0.0, 1.0, 5.0), 1e-3);
+        assert_close(path.key_at(5.0).lookfrom, Vec3::new(3.0, 1.0, 5.0), 1e-3);
+        // Keys on a line at a constant speed stay on it.
+        assert_close(path.key_at(2.0).lookfrom, Vec3::new(2.0, 1.0, 5.0), 1e-3);
+        assert!((path.key_at(2.0).vfov - 50.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_turntable() {
+        let lookat = Vec3::new(0.0, 1.0, 0.0);
+        let path = CameraPath::turntable(
+            Vec3::new(0.0, 1.0, 4.0),
+            lookat,
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            1.0,
+            8.0,
+        );
+        assert_close(path.key_at(2.0).lookfrom, Vec3::new(4.0, 1.0, 0.0), 1e-3);
+        assert_close(path.key_at(8.0).lookfrom, Vec3::new(0.0, 1.0, 4.0), 1e-3);
+        for i in 0..50 {
+            let key = path.key_at(i as f32 * 0.37);
+            assert!(((key.lookfrom - lookat).length() - 4.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_frame_path() {
+        assert_eq!(frame_path("frames/f_####.png", 7), "frames/f_0007.png");
+        assert_eq!(frame_path("f##.png", 123), "f123.png");
+        assert_eq!(frame_path("out.png", 3), "out0003.png");
+    }
+
+    #[test]
+    fn test_sequence() {
+        let path = CameraPath::turntable(
+            Vec3::new(0.0, 0.0, 4.0),
+            Vec3::zeros(),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            2.0,
+            1.0,
+        );
+        let sequence = Sequence {
+            n_frames: 3,
+            fps: 4.0,
+            n_passes: 2,
+            seed: 1,
+        };
+        let mut frames = vec![];
+        sequence
+            .render(
+                4,
+                2,
+                &Surfaces::new(vec![]),
+                &RenderSettings::default(),
+                |time| path.camera_at(time),
+                |frame, image| {
+                    frames.push((frame, image.n_samples()));
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(frames, [(0, 2), (1, 2), (2, 2)]);
+    }
+}
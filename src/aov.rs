@@ -0,0 +1,118 @@
+use crate::{surfaces::HitRes, vec3::Vec3};
+
+/// Auxiliary per-pixel buffers recorded at the first hit of the camera rays.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Aov {
+    /// Material color, black for rays leaving the scene.
+    Albedo,
+    /// World space shading normal, zero for rays leaving the scene.
+    Normal,
+    /// Distance from the camera, infinite if no sample hit anything.
+    Depth,
+    /// World space position, averaged over the samples hitting a surface.
+    Position,
+    /// Index of the surface hit by the first sample, -1 for none.
+    ObjectId,
+    /// `Material::id` of the surface hit by the first sample, -1 for none.
+    MaterialId,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 6] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Position,
+        Aov::ObjectId,
+        Aov::MaterialId,
+    ];
+
+    /// OpenEXR channel names of the AOV layer.
+    pub fn channel_names(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Albedo => &["albedo.R", "albedo.G", "albedo.B"],
+            Aov::Normal => &["N.X", "N.Y", "N.Z"],
+            Aov::Depth => &["Z"],
+            Aov::Position => &["P.X", "P.Y", "P.Z"],
+            Aov::ObjectId => &["objectId"],
+            Aov::MaterialId => &["materialId"],
+        }
+    }
+
+    /// IDs must stay exact, so they are never stored as halfs.
+    pub fn is_id(&self) -> bool {
+        matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+}
+
+/// AOV accumulator of a pixel. Albedo and normal are averaged over all the
+/// samples like the beauty pass, depth and position over the hitting ones.
+#[derive(Clone)]
+pub(crate) struct AovSamples {
+    pub albedo_sum: Vec3,
+    pub normal_sum: Vec3,
+    pub depth_sum: f32,
+    pub position_sum: Vec3,
+    pub n_hits: usize,
+    pub object_id: Option<usize>,
+    pub material_id: Option<usize>,
+}
+
+impl AovSamples {
+    pub fn new() -> Self {
+        Self {
+            albedo_sum: Vec3::zeros(),
+            normal_sum: Vec3::zeros(),
+            depth_sum: 0.0,
+            position_sum: Vec3::zeros(),
+            n_hits: 0,
+            object_id: None,
+            material_id: None,
+        }
+    }
+
+    /// Records the first hit of the sample with index `sample_idx`.
+    pub fn add(&mut self, hit_res: Option<&HitRes>, sample_idx: usize) {
+        if let Some(hit_res) = hit_res {
+            self.albedo_sum += hit_res.material.albedo();
+            self.normal_sum += hit_res.norm;
+            self.depth_sum += hit_res.distance;
+            self.position_sum += hit_res.point;
+            self.n_hits += 1;
+        }
+        if sample_idx == 0 {
+            self.object_id = hit_res.map(|hit_res| hit_res.object_id);
+            self.material_id = hit_res.map(|hit_res| hit_res.material.id());
+        }
+    }
+
+    /// Value of `aov` over `n_samples` samples; scalars fill all channels.
+    pub fn get(&self, aov: Aov, n_samples: usize) -> Vec3 {
+        let mean = |sum: Vec3, n: usize| {
+            if n == 0 {
+                Vec3::zeros()
+            } else {
+                sum.scale(1.0 / n as f32)
+            }
+        };
+        let id = |id: Option<usize>| {
+            let id = id.map_or(-1.0, |id| id as f32);
+            Vec3::new(id, id, id)
+        };
+        match aov {
+            Aov::Albedo => mean(self.albedo_sum, n_samples),
+            Aov::Normal => mean(self.normal_sum, n_samples),
+            Aov::Depth => {
+                let depth = if self.n_hits == 0 {
+                    f32::INFINITY
+                } else {
+                    self.depth_sum / self.n_hits as f32
+                };
+                Vec3::new(depth, depth, depth)
+            }
+            Aov::Position => mean(self.position_sum, self.n_hits),
+            Aov::ObjectId => id(self.object_id),
+            Aov::MaterialId => id(self.material_id),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::f32::consts::PI;
+
+use crate::{buffer::ColorBuffer, camera::rnd_vec_in_disc, rnd, vec3::Vec3};
+
+/// Shape of a lens opening, which out-of-focus highlights (bokeh) take.
+#[derive(Clone)]
+pub enum ApertureShape {
+    Circle,
+    /// Regular polygon formed by `n_blades` straight diaphragm blades,
+    /// rotated by `rotation` degrees.
+    Polygon {
+        n_blades: usize,
+        rotation: f32,
+    },
+    /// Transmission mask covering the square around the opening; white lets
+    /// the light through, black blocks it.
+    Mask(ColorBuffer),
+}
+
+impl ApertureShape {
+    /// Panics if the shape lets no light through.
+    pub(crate) fn validate(&self) {
+        match self {
+            ApertureShape::Circle => {}
+            ApertureShape::Polygon { n_blades, .. } => {
+                if *n_blades < 3 {
+                    panic!("n_blades must be at least 3!");
+                }
+            }
+            ApertureShape::Mask(mask) => {
+                if !mask.colors.iter().any(|color| transmission(color) > 0.0) {
+                    panic!("aperture mask must not be black!");
+                }
+            }
+        }
+    }
+
+    /// Uniformly distributed point of the opening within the unit disc (or
+    /// the [-1, 1]² square for masks), `z` is zero. The shape must have
+    /// passed `validate`.
+    pub(crate) fn sample(&self) -> Vec3 {
+        match self {
+            ApertureShape::Circle => rnd_vec_in_disc(),
+            ApertureShape::Polygon { n_blades, rotation } => {
+                // Triangle fan around the center, all of equal area.
+                let side = ((rnd::gen_f32() * *n_blades as f32) as usize).min(n_blades - 1);
+                let angle =
+                    |k: usize| rotation * PI / 180.0 + 2.0 * PI * k as f32 / *n_blades as f32;
+                let (a, b) = (angle(side), angle(side + 1));
+                let (mut s, mut t) = (rnd::gen_f32(), rnd::gen_f32());
+                if s + t > 1.0 {
+                    (s, t) = (1.0 - s, 1.0 - t);
+                }
+                Vec3::new(s * a.cos() + t * b.cos(), s * a.sin() + t * b.sin(), 0.0)
+            }
+            ApertureShape::Mask(mask) => loop {
+                let (x, y) = (2.0 * rnd::gen_f32() - 1.0, 2.0 * rnd::gen_f32() - 1.0);
+                let column = ((0.5 * (x + 1.0) * mask.width as f32) as usize).min(mask.width - 1);
+                let row = ((0.5 * (1.0 - y) * mask.height as f32) as usize).min(mask.height - 1);
+                if rnd::gen_f32() < transmission(&mask.get(column, row)) {
+                    break Vec3::new(x, y, 0.0);
+                }
+            },
+        }
+    }
+}
+
+fn transmission(color: &Vec3) -> f32 {
+    ((color.x() + color.y() + color.z()) / 3.0).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circle() {
+        let mut quadrants = [false; 4];
+        for i in 0..1000 {
+            rnd::seed(i);
+            let p = ApertureShape::Circle.sample();
+            assert!(p.length() < 1.0);
+            quadrants[usize::from(p.x() > 0.0) + 2 * usize::from(p.y() > 0.0)] = true;
+        }
+        assert_eq!(quadrants, [true; 4]);
+    }
+
+    #[test]
+    fn test_polygon() {
+        let shape = ApertureShape::Polygon {
+            n_blades: 6,
+            rotation: 30.0,
+        };
+        shape.validate();
+        let apothem = (PI / 6.0).cos();
+        let mut max_x = 0.0f32;
+        for i in 0..1000 {
+            rnd::seed(i);
+            let p = shape.sample();
+            for k in 0..6 {
+                // Edge normals of the hexagon with vertices at 30° + k * 60°.
+                let normal = (60.0 + 60.0 * k as f32) * PI / 180.0;
+                assert!(p.x() * normal.cos() + p.y() * normal.sin() <= apothem + 1e-5);
+            }
+            max_x = max_x.max(p.x());
+        }
+        // Flat side on the right.
+        assert!(max_x <= apothem + 1e-5 && max_x > 0.8);
+    }
+
+    #[test]
+    fn test_mask() {
+        let mut colors = vec![Vec3::zeros(); 16];
+        for row in 0..4 {
+            colors[row * 4] = Vec3::ones();
+        }
+        let shape = ApertureShape::Mask(ColorBuffer::new(4, 4, colors));
+        shape.validate();
+        for i in 0..100 {
+            rnd::seed(i);
+            assert!(shape.sample().x() < -0.5);
+        }
+    }
+}
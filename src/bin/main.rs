@@ -1,18 +1,49 @@
-use std::time;
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time,
+};
 
-use rust_raytracer::{camera::Camera, image::Image, surfaces::Surfaces, vec3::Vec3};
+use rust_raytracer::{
+    camera::PerspectiveCamera,
+    image::{Image, RenderSettings},
+    surfaces::Surfaces,
+    vec3::Vec3,
+};
+
+const CHECKPOINT_PATH: &str = "./render.ckpt";
 
 fn main() {
     let start = time::Instant::now();
 
-    let n_threads = 12;
+    let last_percent = AtomicUsize::new(0);
+    let settings = RenderSettings {
+        on_progress: Some(Box::new(move |progress| {
+            let percent = (progress.fraction() * 100.0) as usize;
+            if percent > last_percent.fetch_max(percent, Ordering::Relaxed) {
+                let eta = progress.eta().unwrap_or_default().as_secs();
+                print!(
+                    "\r{:3}% {:8.0} samples/s ETA {}s   ",
+                    percent,
+                    progress.samples_per_sec(),
+                    eta
+                );
+                io::stdout().flush().unwrap();
+            }
+        })),
+        ..Default::default()
+    };
+    let seed = 42;
+    let n_passes = 100;
     let nx = 400;
     let ny = 200;
     let vfov = 30.0;
     let lookfrom = Vec3::new(15.0, 5.0, 5.0);
     let lookat = Vec3::new(0.0, 0.0, -1.0);
 
-    let camera = Camera::new(
+    let camera = PerspectiveCamera::new(
         lookfrom,
         lookat,
         Vec3::new(0.0, 1.0, 0.0),
@@ -22,10 +53,34 @@ fn main() {
         (lookfrom - lookat).length(),
     );
 
-    let surfaces = Surfaces::generate();
-    let mut image = Image::new(nx, ny);
-    image.render(&camera, &surfaces, n_threads);
-    image.to_ppm("./render.ppm".to_string());
+    let surfaces = Surfaces::generate_with_seed(seed);
+    let mut image = Image::with_seed(nx, ny, seed);
+    if Path::new(CHECKPOINT_PATH).exists() {
+        let checkpoint = Image::load_checkpoint(CHECKPOINT_PATH).unwrap();
+        if (checkpoint.width(), checkpoint.height(), checkpoint.seed()) == (nx, ny, seed) {
+            println!("Resuming from {} samples", checkpoint.n_samples());
+            image = checkpoint;
+        }
+    }
+
+    let n_passes_left = n_passes - image.n_samples().min(n_passes);
+    image.render_progressive(
+        &camera,
+        &surfaces,
+        &settings,
+        n_passes_left,
+        |image, pass| {
+            if pass % 10 == 0 {
+                image.save("./render.png").unwrap();
+                image.save_checkpoint(CHECKPOINT_PATH).unwrap();
+            }
+            true
+        },
+    );
+    image.save("./render.png").unwrap();
+    if Path::new(CHECKPOINT_PATH).exists() {
+        fs::remove_file(CHECKPOINT_PATH).unwrap();
+    }
 
-    println!("Elapsed time: {}s", start.elapsed().as_secs());
+    println!("\nElapsed time: {}s", start.elapsed().as_secs());
 }
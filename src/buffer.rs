@@ -0,0 +1,125 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::Path,
+};
+
+use crate::{
+    exr::{write_exr, ExrChannel, ExrOptions},
+    hdr::{read_hdr, write_hdr},
+    png::{read_png, write_png, PngOptions},
+    ppm::{read_pfm, read_ppm, write_pfm, write_ppm, PpmFormat},
+    vec3::Vec3,
+};
+
+/// Plain RGB image loaded from a file, rows top to bottom.
+#[derive(Clone, Debug)]
+pub struct ColorBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub colors: Vec<Vec3>,
+}
+
+impl ColorBuffer {
+    pub fn new(width: usize, height: usize, colors: Vec<Vec3>) -> Self {
+        if colors.len() != width * height {
+            panic!("colors must match the image size!");
+        }
+        Self {
+            width,
+            height,
+            colors,
+        }
+    }
+
+    /// Loads an image in the format given by the file extension: `png`,
+    /// `ppm`, `pfm` or `hdr`. EXR files can't be read. PNG and PPM hold
+    /// display values in [0, 1], PFM and HDR linear radiance, see
+    /// `is_linear_format`.
+    pub fn load<P: AsRef<Path>>(file_path: P) -> io::Result<Self> {
+        let file_path = file_path.as_ref();
+        let inp = BufReader::new(File::open(file_path)?);
+        match get_extension(file_path).as_deref() {
+            Some("png") => read_png(inp),
+            Some("ppm") => read_ppm(inp),
+            Some("pfm") => read_pfm(inp),
+            Some("hdr") => read_hdr(inp),
+            _ => Err(unsupported_format(file_path)),
+        }
+    }
+
+    /// Saves the image in the format given by the file extension. Display
+    /// formats (`png`, `ppm`) expect values in [0, 1], float formats (`pfm`,
+    /// `exr`, `hdr`) store them as they are.
+    pub fn save<P: AsRef<Path>>(&self, file_path: P) -> io::Result<()> {
+        let file_path = file_path.as_ref();
+        let extension = get_extension(file_path);
+        if !matches!(
+            extension.as_deref(),
+            Some("png" | "ppm" | "pfm" | "exr" | "hdr")
+        ) {
+            return Err(unsupported_format(file_path));
+        }
+
+        let (width, height) = (self.width, self.height);
+        let out = BufWriter::new(File::create(file_path)?);
+        match extension.as_deref() {
+            Some("png") => write_png(
+                out,
+                width,
+                height,
+                &self.colors,
+                &[],
+                &PngOptions::default(),
+            ),
+            Some("ppm") => write_ppm(out, width, height, &self.colors, PpmFormat::Binary),
+            Some("pfm") => write_pfm(out, width, height, &self.colors),
+            Some("hdr") => write_hdr(out, width, height, &self.colors),
+            _ => {
+                let options = ExrOptions::default();
+                let channels: Vec<ExrChannel> = ["R", "G", "B"]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| {
+                        let values = self.colors.iter().map(|color| color[i]).collect();
+                        ExrChannel::new(name, options.pixel_type, values)
+                    })
+                    .collect();
+                write_exr(out, width, height, &channels, options.compression)
+            }
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Vec3 {
+        self.colors[y * self.width + x]
+    }
+}
+
+/// Lowercase extension of `file_path`, used to pick the image format.
+pub fn get_extension(file_path: &Path) -> Option<String> {
+    file_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase())
+}
+
+/// Whether the format of `file_path` stores linear radiance (`pfm`, `exr`,
+/// `hdr`) rather than display values (`png`, `ppm`).
+pub fn is_linear_format(file_path: &Path) -> bool {
+    matches!(
+        get_extension(file_path).as_deref(),
+        Some("pfm" | "exr" | "hdr")
+    )
+}
+
+/// Error for a malformed file.
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub fn unsupported_format(file_path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("unsupported image format: {}", file_path.display()),
+    )
+}
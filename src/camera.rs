@@ -1,21 +1,71 @@
 use std::f32::consts::PI;
 
-use rand::Rng;
+use crate::{aperture::ApertureShape, ray::Ray, rnd, vec3::Vec3};
 
-use crate::{ray::Ray, vec3::Vec3};
+/// Position on the film a camera ray is generated for.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraSample {
+    /// Horizontal film coordinate in [0, 1], left to right.
+    pub u: f32,
+    /// Vertical film coordinate in [0, 1], bottom to top.
+    pub v: f32,
+}
+
+impl CameraSample {
+    pub fn new(u: f32, v: f32) -> Self {
+        Self { u, v }
+    }
+}
+
+/// Maps film samples to primary rays. Cameras draw any extra randomness
+/// (e.g. lens samples) from `rnd`.
+pub trait Camera: Sync {
+    /// Ray through the film position of `sample`, `None` where the film
+    /// isn't covered by the projection (e.g. outside a fisheye circle) or
+    /// the lens blocks the ray (vignetting); only in the latter case
+    /// `center_ray` gives a ray.
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray>;
+
+    /// Deterministic ray through the center of the lens (the chief ray) for
+    /// queries like `Image::pick`; leaves `rnd` untouched. Cameras without a
+    /// lens return the ray of `generate_ray`.
+    fn center_ray(&self, sample: &CameraSample) -> Option<Ray>;
+
+    /// Film position (`u`, `v` as in `CameraSample`) where `point` shows up
+    /// and its depth, `None` if it's out of view. Cameras without a single
+    /// center of projection (stereo pairs, real lenses) don't project.
+    fn project(&self, _point: &Vec3) -> Option<(f32, f32, f32)> {
+        None
+    }
+}
+
+/// Orthonormal camera basis (right, up, backward) looking from `lookfrom`
+/// towards `lookat`.
+pub(crate) fn look_at_basis(lookfrom: Vec3, lookat: Vec3, vup: Vec3) -> (Vec3, Vec3, Vec3) {
+    let w = (lookfrom - lookat).get_unit();
+    let u = vup.cross(&w).get_unit();
+    let v = w.cross(&u);
+    (u, v, w)
+}
 
+/// Thin lens perspective camera with depth of field.
 #[derive(Clone)]
-pub struct Camera {
+pub struct PerspectiveCamera {
     corner: Vec3,
     horizontal: Vec3,
     vertical: Vec3,
     origin: Vec3,
     lens_radius: f32,
+    aperture_shape: ApertureShape,
+    cat_eye: f32,
+    aspect: f32,
+    half_height: f32,
     u: Vec3,
     v: Vec3,
+    w: Vec3,
 }
 
-impl Camera {
+impl PerspectiveCamera {
     pub fn new(
         lookfrom: Vec3,
         lookat: Vec3,
@@ -30,9 +80,7 @@ impl Camera {
         let half_height = (theta / 2.0).tan();
         let half_width = aspect * half_height;
 
-        let w = (lookfrom - lookat).get_unit();
-        let u = vup.cross(&w).get_unit();
-        let v = w.cross(&u);
+        let (u, v, w) = look_at_basis(lookfrom, lookat, vup);
 
         let origin = lookfrom;
         let corner = origin
@@ -48,12 +96,35 @@ impl Camera {
             vertical,
             origin,
             lens_radius,
+            aperture_shape: ApertureShape::Circle,
+            cat_eye: 0.0,
+            aspect,
+            half_height,
             u,
             v,
+            w,
+        }
+    }
+
+    pub fn set_aperture_shape(&mut self, aperture_shape: ApertureShape) {
+        aperture_shape.validate();
+        self.aperture_shape = aperture_shape;
+    }
+
+    /// Clips the aperture off-axis by the lens barrel, seen as a unit disc
+    /// shifting by `cat_eye` aperture radii at the image corners. Bokeh turns
+    /// into cat's eyes towards the edges, which also darken. Zero disables.
+    pub fn set_cat_eye(&mut self, cat_eye: f32) {
+        if cat_eye < 0.0 {
+            panic!("cat_eye must not be negative!");
         }
+        self.cat_eye = cat_eye;
     }
-    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
-        let rd = rnd_vec_in_disc().scale(self.lens_radius);
+
+    /// Ray through the film at `u`, `v` leaving the lens at `aperture_point`
+    /// of the unit opening.
+    fn lens_ray(&self, u: f32, v: f32, aperture_point: Vec3) -> Ray {
+        let rd = aperture_point.scale(self.lens_radius);
         let offset = self.u.scale(rd.x()) + self.v.scale(rd.y());
 
         let horizontal = self.horizontal.scale(u);
@@ -64,15 +135,178 @@ impl Camera {
     }
 }
 
-fn rnd_vec_in_disc() -> Vec3 {
-    let mut rng = rand::thread_rng();
+impl Camera for PerspectiveCamera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        let (u, v) = (sample.u, sample.v);
+        let aperture_point = self.aperture_shape.sample();
+        if self.cat_eye > 0.0 {
+            let diagonal = (1.0 + self.aspect * self.aspect).sqrt();
+            let barrel = Vec3::new((2.0 * u - 1.0) * self.aspect, 2.0 * v - 1.0, 0.0)
+                .scale(self.cat_eye / diagonal);
+            if (aperture_point - barrel).length() > 1.0 {
+                return None;
+            }
+        }
+        Some(self.lens_ray(u, v, aperture_point))
+    }
+
+    fn center_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        Some(self.lens_ray(sample.u, sample.v, Vec3::zeros()))
+    }
+
+    /// Projects through the center of the lens, `depth` is the distance
+    /// along the viewing direction.
+    fn project(&self, point: &Vec3) -> Option<(f32, f32, f32)> {
+        let d = *point - self.origin;
+        let depth = -d.dot(&self.w);
+        if depth <= 0.0 {
+            return None;
+        }
+        let u = 0.5 + d.dot(&self.u) / (depth * 2.0 * self.aspect * self.half_height);
+        let v = 0.5 + d.dot(&self.v) / (depth * 2.0 * self.half_height);
+        on_film(u, v, depth)
+    }
+}
+
+/// Keeps film positions within the image.
+pub(crate) fn on_film(u: f32, v: f32, depth: f32) -> Option<(f32, f32, f32)> {
+    ((0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v)).then_some((u, v, depth))
+}
+
+/// Parallel projection camera for technical views, free of perspective
+/// distortion.
+#[derive(Clone)]
+pub struct OrthographicCamera {
+    corner: Vec3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    direction: Vec3,
+}
+
+impl OrthographicCamera {
+    /// Camera looking from `lookfrom` towards `lookat` whose film covers
+    /// `view_height` world units vertically.
+    pub fn new(lookfrom: Vec3, lookat: Vec3, vup: Vec3, view_height: f32, aspect: f32) -> Self {
+        if view_height <= 0.0 {
+            panic!("view_height must be positive!");
+        }
+        let (u, v, w) = look_at_basis(lookfrom, lookat, vup);
+
+        let horizontal = u.scale(aspect * view_height);
+        let vertical = v.scale(view_height);
+        let corner = lookfrom - horizontal.scale(0.5) - vertical.scale(0.5);
+
+        Self {
+            corner,
+            horizontal,
+            vertical,
+            direction: -w,
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        let origin = self.corner + self.horizontal.scale(sample.u) + self.vertical.scale(sample.v);
+        Some(Ray::new(origin, self.direction))
+    }
+
+    fn center_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        self.generate_ray(sample)
+    }
+
+    fn project(&self, point: &Vec3) -> Option<(f32, f32, f32)> {
+        let d = *point - self.corner;
+        let depth = d.dot(&self.direction);
+        if depth <= 0.0 {
+            return None;
+        }
+        let u = d.dot(&self.horizontal) / self.horizontal.squared_length();
+        let v = d.dot(&self.vertical) / self.vertical.squared_length();
+        on_film(u, v, depth)
+    }
+}
+
+pub(crate) fn rnd_vec_in_disc() -> Vec3 {
     let mut p: Vec3;
     let ones = Vec3::new(1.0, 1.0, 0.0);
     loop {
-        p = Vec3::new(rng.gen::<f32>(), rng.gen::<f32>(), 0.0);
+        p = Vec3::new(rnd::gen_f32(), rnd::gen_f32(), 0.0).scale(2.0);
         p -= ones;
         if p.length() < 1.0 {
             break p;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_orthographic_camera() {
+        let camera = OrthographicCamera::new(
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            2.0,
+            2.0,
+        );
+        let ray = camera.generate_ray(&CameraSample::new(0.5, 0.5)).unwrap();
+        assert_eq!(ray.origin, Vec3::new(0.0, 0.0, 5.0));
+        assert_eq!(ray.direction, Vec3::new(0.0, 0.0, -1.0));
+        // Parallel rays from the film corners.
+        let ray = camera.generate_ray(&CameraSample::new(1.0, 0.0)).unwrap();
+        assert_eq!(ray.origin, Vec3::new(2.0, -1.0, 5.0));
+        assert_eq!(ray.direction, Vec3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn test_project() {
+        let (lookfrom, lookat, vup) = (
+            Vec3::new(1.0, 2.0, 3.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        let cameras: [Box<dyn Camera>; 2] = [
+            Box::new(PerspectiveCamera::new(
+                lookfrom, lookat, vup, 40.0, 2.0, 0.0, 1.0,
+            )),
+            Box::new(OrthographicCamera::new(lookfrom, lookat, vup, 3.0, 2.0)),
+        ];
+        for camera in cameras {
+            let ray = camera.generate_ray(&CameraSample::new(0.2, 0.9)).unwrap();
+            let (u, v, depth) = camera.project(&ray.get_point(5.0)).unwrap();
+            assert!((u - 0.2).abs() < 1e-4 && (v - 0.9).abs() < 1e-4);
+            assert!(depth > 0.0 && depth <= 5.0);
+            // Behind the camera.
+            assert!(camera.project(&ray.get_point(-5.0)).is_none());
+        }
+    }
+
+    #[test]
+    fn test_cat_eye() {
+        let mut camera = PerspectiveCamera::new(
+            Vec3::zeros(),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+            1.5,
+            0.5,
+            4.0,
+        );
+        camera.set_cat_eye(1.0);
+        let n_passed = |u, v| {
+            (0..200)
+                .filter(|&i| {
+                    rnd::seed(i);
+                    camera.generate_ray(&CameraSample::new(u, v)).is_some()
+                })
+                .count()
+        };
+        assert_eq!(n_passed(0.5, 0.5), 200);
+        // Half the aperture is clipped at the corners.
+        let corner = n_passed(1.0, 1.0);
+        assert!(corner > 40 && corner < 120);
+    }
+}
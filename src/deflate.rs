@@ -0,0 +1,440 @@
+use std::io;
+
+use crate::buffer::invalid_data;
+
+const WINDOW_SIZE: usize = 1 << 15;
+const HASH_BITS: usize = 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order the code length code lengths of a dynamic block are stored in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Compresses `data` into a zlib stream: LZ77 matching over a 32K window,
+/// encoded as a single deflate block with the fixed Huffman codes.
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter::new();
+    out.bytes.extend_from_slice(&[0x78, 0x9C]);
+
+    // BFINAL = 1, BTYPE = 01 (fixed Huffman codes).
+    out.write_bits(1, 1);
+    out.write_bits(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = find_match(data, pos, &head, &prev);
+        if length >= MIN_MATCH {
+            write_match(&mut out, length, distance);
+        } else {
+            write_literal(&mut out, data[pos] as u16);
+        }
+        let next_pos = pos + length.max(1);
+        while pos < next_pos {
+            insert_hash(data, pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+    write_literal(&mut out, 256);
+
+    let mut bytes = out.finish();
+    bytes.extend_from_slice(&adler32(data).to_be_bytes());
+    bytes
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// Decompresses a zlib stream with stored, fixed or dynamic Huffman
+/// blocks, checking its Adler-32 checksum.
+pub fn zlib_decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() < 6
+        || data[0] & 0x0F != 8
+        || data[1] & 0x20 != 0
+        || !u16::from_be_bytes([data[0], data[1]]).is_multiple_of(31)
+    {
+        return Err(invalid_data("bad zlib header"));
+    }
+    let mut inp = BitReader::new(&data[2..]);
+    let mut out = vec![];
+    loop {
+        let is_last = inp.read_bits(1)? == 1;
+        match inp.read_bits(2)? {
+            0 => {
+                inp.align();
+                let length = inp.read_bits(16)?;
+                if inp.read_bits(16)? != !length & 0xFFFF {
+                    return Err(invalid_data("bad stored block length"));
+                }
+                for _ in 0..length {
+                    out.push(inp.read_bits(8)? as u8);
+                }
+            }
+            1 => {
+                let mut lengths = [8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                let literals = Huffman::new(&lengths);
+                let distances = Huffman::new(&[5; 30]);
+                inflate_block(&mut inp, &literals, &distances, &mut out)?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(&mut inp)?;
+                inflate_block(&mut inp, &literals, &distances, &mut out)?;
+            }
+            _ => return Err(invalid_data("bad deflate block type")),
+        }
+        if is_last {
+            break;
+        }
+    }
+
+    inp.align();
+    let mut checksum = 0;
+    for _ in 0..4 {
+        checksum = checksum << 8 | inp.read_bits(8)?;
+    }
+    if checksum != adler32(&out) {
+        return Err(invalid_data("zlib checksum mismatch"));
+    }
+    Ok(out)
+}
+
+/// Reads the literal/length and distance codes of a dynamic block.
+fn read_dynamic_codes(inp: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let n_literals = inp.read_bits(5)? as usize + 257;
+    let n_distances = inp.read_bits(5)? as usize + 1;
+    let n_code_lengths = inp.read_bits(4)? as usize + 4;
+    let mut code_lengths = [0; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..n_code_lengths] {
+        code_lengths[symbol] = inp.read_bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    let n_codes = n_literals + n_distances;
+    let mut lengths = Vec::with_capacity(n_codes);
+    while lengths.len() < n_codes {
+        let (value, n_repeats) = match code_lengths.decode(inp)? {
+            length @ 0..=15 => (length as u8, 1),
+            16 => {
+                let &prev = lengths
+                    .last()
+                    .ok_or_else(|| invalid_data("repeat without a code length"))?;
+                (prev, 3 + inp.read_bits(2)?)
+            }
+            17 => (0, 3 + inp.read_bits(3)?),
+            _ => (0, 11 + inp.read_bits(7)?),
+        };
+        lengths.extend((0..n_repeats).map(|_| value));
+    }
+    if lengths.len() > n_codes || lengths[256] == 0 {
+        return Err(invalid_data("bad dynamic Huffman codes"));
+    }
+    Ok((
+        Huffman::new(&lengths[..n_literals]),
+        Huffman::new(&lengths[n_literals..]),
+    ))
+}
+
+/// Decodes the symbols of a compressed block up to its end of block code.
+fn inflate_block(
+    inp: &mut BitReader,
+    literals: &Huffman,
+    distances: &Huffman,
+    out: &mut Vec<u8>,
+) -> io::Result<()> {
+    loop {
+        let symbol = literals.decode(inp)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let idx = symbol - 257;
+        if idx >= LENGTH_BASE.len() {
+            return Err(invalid_data("bad deflate length code"));
+        }
+        let length = LENGTH_BASE[idx] as usize + inp.read_bits(LENGTH_EXTRA[idx])? as usize;
+        let idx = distances.decode(inp)? as usize;
+        if idx >= DIST_BASE.len() {
+            return Err(invalid_data("bad deflate distance code"));
+        }
+        let distance = DIST_BASE[idx] as usize + inp.read_bits(DIST_EXTRA[idx])? as usize;
+        if distance > out.len() {
+            return Err(invalid_data("deflate distance too far back"));
+        }
+        // Byte by byte, as the match may overlap the bytes it produces.
+        for _ in 0..length {
+            out.push(out[out.len() - distance]);
+        }
+    }
+}
+
+/// Canonical Huffman code given by the code length of every symbol.
+struct Huffman {
+    /// Number of codes of every length.
+    counts: [u16; 16],
+    /// Symbols ordered by code.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16)
+            .filter(|&symbol| lengths[symbol as usize] > 0)
+            .collect();
+        symbols.sort_by_key(|&symbol| lengths[symbol as usize]);
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, inp: &mut BitReader) -> io::Result<u16> {
+        // Codes of a length are consecutive, starting at `first`.
+        let (mut code, mut first, mut idx) = (0, 0, 0);
+        for &count in &self.counts[1..] {
+            code |= inp.read_bits(1)? as usize;
+            let count = count as usize;
+            if code < first + count {
+                return Ok(self.symbols[idx + code - first]);
+            }
+            idx += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid_data("bad Huffman code"))
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    /// Position in bits.
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    /// Reads `n_bits`, least significant bit first.
+    fn read_bits(&mut self, n_bits: u8) -> io::Result<u32> {
+        let mut value = 0;
+        for i in 0..n_bits {
+            let byte = self
+                .bytes
+                .get(self.pos / 8)
+                .ok_or_else(|| invalid_data("truncated deflate stream"))?;
+            value |= ((byte >> (self.pos % 8)) as u32 & 1) << i;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+
+    /// Skips to the next byte boundary.
+    fn align(&mut self) {
+        self.pos = self.pos.next_multiple_of(8);
+    }
+}
+
+fn hash3(data: &[u8], pos: usize) -> usize {
+    let value = (data[pos] as usize) << 16 | (data[pos + 1] as usize) << 8 | data[pos + 2] as usize;
+    (value.wrapping_mul(2654435761) >> 8) & ((1 << HASH_BITS) - 1)
+}
+
+fn insert_hash(data: &[u8], pos: usize, head: &mut [usize], prev: &mut [usize]) {
+    if pos + MIN_MATCH <= data.len() {
+        let hash = hash3(data, pos);
+        prev[pos] = head[hash];
+        head[hash] = pos;
+    }
+}
+
+fn find_match(data: &[u8], pos: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    if pos + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+    let max_length = MAX_MATCH.min(data.len() - pos);
+    let (mut best_length, mut best_distance) = (0, 0);
+    let mut candidate = head[hash3(data, pos)];
+    for _ in 0..MAX_CHAIN {
+        if candidate == usize::MAX || pos - candidate > WINDOW_SIZE {
+            break;
+        }
+        let length = data[candidate..]
+            .iter()
+            .zip(&data[pos..pos + max_length])
+            .take_while(|(a, b)| a == b)
+            .count();
+        if length > best_length {
+            (best_length, best_distance) = (length, pos - candidate);
+            if length == max_length {
+                break;
+            }
+        }
+        candidate = prev[candidate];
+    }
+    (best_length, best_distance)
+}
+
+fn write_literal(out: &mut BitWriter, value: u16) {
+    let (code, n_bits) = match value {
+        0..=143 => (0x30 + value, 8),
+        144..=255 => (0x190 + value - 144, 9),
+        256..=279 => (value - 256, 7),
+        _ => (0xC0 + value - 280, 8),
+    };
+    out.write_code(code as u32, n_bits);
+}
+
+fn write_match(out: &mut BitWriter, length: usize, distance: usize) {
+    let idx = LENGTH_BASE
+        .iter()
+        .rposition(|&base| base as usize <= length)
+        .unwrap();
+    write_literal(out, 257 + idx as u16);
+    out.write_bits(
+        (length - LENGTH_BASE[idx] as usize) as u32,
+        LENGTH_EXTRA[idx],
+    );
+
+    let idx = DIST_BASE
+        .iter()
+        .rposition(|&base| base as usize <= distance)
+        .unwrap();
+    out.write_code(idx as u32, 5);
+    out.write_bits((distance - DIST_BASE[idx] as usize) as u32, DIST_EXTRA[idx]);
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    n_bits: u8,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            bytes: vec![],
+            acc: 0,
+            n_bits: 0,
+        }
+    }
+
+    /// Writes `n_bits` of `value`, least significant bit first.
+    fn write_bits(&mut self, value: u32, n_bits: u8) {
+        self.acc |= value << self.n_bits;
+        self.n_bits += n_bits;
+        while self.n_bits >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.n_bits -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which deflate stores most significant bit first.
+    fn write_code(&mut self, code: u32, n_bits: u8) {
+        self.write_bits(code.reverse_bits() >> (32 - n_bits), n_bits);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.n_bits > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn test_zlib_decompress() {
+        let data: Vec<u8> = b"abcabcabcabc-raytracer-".repeat(100);
+        assert_eq!(zlib_decompress(&zlib_compress(&data)).unwrap(), data);
+
+        // Streams from zlib with a stored and with a dynamic Huffman block.
+        let stored = [
+            0x78, 0x01, 0x01, 0x07, 0x00, 0xF8, 0xFF, 0x73, 0x74, 0x6F, 0x72, 0x65, 0x64, 0x21,
+            0x0B, 0xEF, 0x02, 0xB3,
+        ];
+        assert_eq!(zlib_decompress(&stored).unwrap(), b"stored!");
+        let text = (0..40)
+            .map(|i| format!("pixel {} color {}", i, i * 37 % 101))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let dynamic = [
+            0x78, 0xDA, 0x3D, 0xD2, 0x4B, 0x0A, 0x83, 0x40, 0x10, 0x06, 0xE1, 0xAB, 0x78, 0x04,
+            0xA7, 0x67, 0x9C, 0xC7, 0x79, 0x42, 0x16, 0x01, 0x41, 0xC9, 0x2A, 0xC7, 0x4F, 0x08,
+            0x5D, 0xB5, 0x12, 0x7F, 0xF8, 0xA0, 0xD0, 0xBE, 0x5F, 0x9F, 0xE7, 0xB9, 0xED, 0xDB,
+            0xE3, 0x3A, 0xAF, 0xF7, 0xEF, 0x79, 0xFF, 0xDF, 0x4B, 0xBE, 0xD7, 0x91, 0x43, 0xE4,
+            0x30, 0x5A, 0x0E, 0x35, 0x87, 0x02, 0x69, 0x39, 0x34, 0xC8, 0x91, 0xC3, 0x84, 0xF4,
+            0x1C, 0x02, 0x32, 0x72, 0x38, 0x20, 0x33, 0x87, 0x05, 0x59, 0x74, 0x18, 0x46, 0x69,
+            0xC7, 0x14, 0x5B, 0x19, 0x68, 0x6D, 0x22, 0x62, 0x87, 0x88, 0xDA, 0xA2, 0x22, 0xF7,
+            0x50, 0xD1, 0x3B, 0x55, 0x04, 0x87, 0x8A, 0xE2, 0xAE, 0x22, 0x79, 0xF9, 0xE9, 0x48,
+            0xAE, 0xA8, 0x20, 0x79, 0xA0, 0x82, 0xE6, 0xCE, 0x40, 0x72, 0x13, 0x91, 0x3C, 0x45,
+            0x24, 0x17, 0x15, 0xC9, 0x87, 0x8A, 0xE4, 0xA5, 0x22, 0x39, 0x54, 0x24, 0x77, 0x54,
+            0xDD, 0xFD, 0xBB, 0xB0, 0xEA, 0x67, 0x86, 0x55, 0x6F, 0x42, 0x46, 0xF4, 0x62, 0xF0,
+            0x28, 0x44, 0x5E, 0x85, 0x88, 0xE6, 0xA2, 0xF2, 0x2E, 0x54, 0x1E, 0x86, 0x8A, 0xE6,
+            0x58, 0x5F, 0x4C, 0x84, 0xDB, 0xD2,
+        ];
+        assert_eq!(zlib_decompress(&dynamic).unwrap(), text.as_bytes());
+
+        let mut corrupt = zlib_compress(&data);
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 1;
+        assert!(zlib_decompress(&corrupt).is_err());
+    }
+
+    #[test]
+    fn test_zlib_compress() {
+        let data: Vec<u8> = b"abcabcabcabc-raytracer-".repeat(100);
+        let compressed = zlib_compress(&data);
+        assert_eq!(&compressed[..2], &[0x78, 0x9C]);
+        assert!(compressed.len() < data.len() / 10);
+        assert_eq!(
+            compressed[compressed.len() - 4..],
+            adler32(&data).to_be_bytes()
+        );
+    }
+}
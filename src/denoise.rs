@@ -0,0 +1,173 @@
+use crate::vec3::Vec3;
+
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Parameters of the edge-avoiding À-Trous wavelet filter.
+#[derive(Clone, Copy, Debug)]
+pub struct DenoiseSettings {
+    /// Number of filter passes; pass `i` uses taps `2^i` pixels apart.
+    pub n_iterations: usize,
+    /// Color edge-stopping strength, halved after every pass.
+    pub sigma_color: f32,
+    pub sigma_albedo: f32,
+    pub sigma_normal: f32,
+    /// Relative depth difference tolerated between neighbours.
+    pub sigma_depth: f32,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        Self {
+            n_iterations: 5,
+            sigma_color: 1.0,
+            sigma_albedo: 0.1,
+            sigma_normal: 0.1,
+            sigma_depth: 0.1,
+        }
+    }
+}
+
+/// Feature buffers guiding the filter, rows top to bottom.
+pub struct DenoiseGuide<'a> {
+    pub albedo: &'a [Vec3],
+    pub normal: &'a [Vec3],
+    /// Distance from the camera, infinite where nothing was hit.
+    pub depth: &'a [f32],
+    /// Pixels holding samples; the others, e.g. outside of a render region,
+    /// are left out of the filter.
+    pub has_samples: &'a [bool],
+}
+
+/// Filters `colors` with the À-Trous wavelet transform, weighting every tap
+/// by its similarity in color, albedo, normal and depth so that edges of
+/// the guide buffers stay sharp.
+pub fn denoise(
+    width: usize,
+    height: usize,
+    colors: &[Vec3],
+    guide: &DenoiseGuide,
+    settings: &DenoiseSettings,
+) -> Vec<Vec3> {
+    let sigmas = [
+        settings.sigma_color,
+        settings.sigma_albedo,
+        settings.sigma_normal,
+        settings.sigma_depth,
+    ];
+    if sigmas.iter().any(|sigma| sigma.is_nan() || *sigma <= 0.0) {
+        panic!("Denoise sigmas must be positive!");
+    }
+    let mut colors = colors.to_vec();
+    let mut sigma_color = settings.sigma_color;
+    for iteration in 0..settings.n_iterations {
+        let step = 1 << iteration;
+        let mut filtered = Vec::with_capacity(colors.len());
+        for y in 0..height {
+            for x in 0..width {
+                let p = y * width + x;
+                if !guide.has_samples[p] {
+                    filtered.push(colors[p]);
+                    continue;
+                }
+                let mut sum = Vec3::zeros();
+                let mut weight_sum = 0.0;
+                for (j, ky) in KERNEL.iter().enumerate() {
+                    let qy = y as isize + (j as isize - 2) * step;
+                    if qy < 0 || qy >= height as isize {
+                        continue;
+                    }
+                    for (i, kx) in KERNEL.iter().enumerate() {
+                        let qx = x as isize + (i as isize - 2) * step;
+                        if qx < 0 || qx >= width as isize {
+                            continue;
+                        }
+                        let q = qy as usize * width + qx as usize;
+                        if !guide.has_samples[q] {
+                            continue;
+                        }
+                        let weight = kx
+                            * ky
+                            * gaussian(colors[p] - colors[q], sigma_color)
+                            * gaussian(guide.albedo[p] - guide.albedo[q], settings.sigma_albedo)
+                            * gaussian(guide.normal[p] - guide.normal[q], settings.sigma_normal)
+                            * depth_weight(guide.depth[p], guide.depth[q], settings.sigma_depth);
+                        sum += colors[q].scale(weight);
+                        weight_sum += weight;
+                    }
+                }
+                filtered.push(sum.scale(1.0 / weight_sum));
+            }
+        }
+        colors = filtered;
+        sigma_color *= 0.5;
+    }
+    colors
+}
+
+fn gaussian(diff: Vec3, sigma: f32) -> f32 {
+    (-diff.squared_length() / (sigma * sigma)).exp()
+}
+
+fn depth_weight(a: f32, b: f32, sigma: f32) -> f32 {
+    match (a.is_finite(), b.is_finite()) {
+        (false, false) => 1.0,
+        (true, true) => {
+            let diff = (a - b).abs() / (sigma * a.max(1.0e-3));
+            (-diff * diff).exp()
+        }
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn test_denoise() {
+        let (width, height) = (32, 16);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut clean = vec![];
+        let mut noisy = vec![];
+        let mut normal = vec![];
+        for _ in 0..height {
+            for x in 0..width {
+                let (color, norm) = if x < width / 2 {
+                    (Vec3::new(0.2, 0.2, 0.2), Vec3::new(1.0, 0.0, 0.0))
+                } else {
+                    (Vec3::new(0.8, 0.5, 0.3), Vec3::new(0.0, 1.0, 0.0))
+                };
+                let noise = rng.gen::<f32>() * 2.0 - 1.0;
+                clean.push(color);
+                noisy.push(color.scale(1.0 + 0.5 * noise));
+                normal.push(norm);
+            }
+        }
+        let albedo = vec![Vec3::ones(); width * height];
+        let depth = vec![5.0; width * height];
+        let has_samples = vec![true; width * height];
+        let guide = DenoiseGuide {
+            albedo: &albedo,
+            normal: &normal,
+            depth: &depth,
+            has_samples: &has_samples,
+        };
+        let denoised = denoise(width, height, &noisy, &guide, &DenoiseSettings::default());
+
+        let error = |colors: &[Vec3]| -> f32 {
+            let sum: f32 = colors
+                .iter()
+                .zip(clean.iter())
+                .map(|(a, b)| (*a - *b).squared_length())
+                .sum();
+            sum / colors.len() as f32
+        };
+        assert!(error(&denoised) < 0.1 * error(&noisy));
+
+        // Pixels next to the normal edge keep the color of their side.
+        let (left, right) = (8 * width + width / 2 - 1, 8 * width + width / 2);
+        assert!((denoised[left] - clean[left]).length() < 0.05);
+        assert!((denoised[right] - clean[right]).length() < 0.1);
+    }
+}
@@ -0,0 +1,96 @@
+use std::{f32::consts::PI, io, path::Path};
+
+use crate::{
+    buffer::{invalid_data, is_linear_format, ColorBuffer},
+    tonemap::srgb_decode,
+    vec3::Vec3,
+};
+
+/// Radiance coming from directions where rays leave the scene.
+pub enum Environment {
+    /// White to light blue vertical gradient.
+    Gradient,
+    /// Equirectangular (latitude-longitude) map, +Y up, with the center of
+    /// the map looking along -Z.
+    Map(ColorBuffer),
+}
+
+impl Environment {
+    /// Loads an equirectangular map, e.g. a Radiance `.hdr` file. Display
+    /// formats (`png`, `ppm`) are decoded from sRGB to linear radiance.
+    pub fn load<P: AsRef<Path>>(file_path: P) -> io::Result<Self> {
+        let file_path = file_path.as_ref();
+        let mut map = ColorBuffer::load(file_path)?;
+        if map.width == 0 || map.height == 0 {
+            return Err(invalid_data("empty environment map"));
+        }
+        if !is_linear_format(file_path) {
+            for color in map.colors.iter_mut() {
+                *color = Vec3::new(
+                    srgb_decode(color.x()),
+                    srgb_decode(color.y()),
+                    srgb_decode(color.z()),
+                );
+            }
+        }
+        Ok(Environment::Map(map))
+    }
+
+    pub fn get_color(&self, direction: &Vec3) -> Vec3 {
+        match self {
+            Environment::Gradient => {
+                let t = 0.5 * (direction.y() + 1.0);
+                Vec3::new(1.0, 1.0, 1.0).scale(1.0 - t) + Vec3::new(0.5, 0.7, 1.0).scale(t)
+            }
+            Environment::Map(map) => {
+                let u = 0.5 + direction.x().atan2(-direction.z()) / (2.0 * PI);
+                let v = direction.y().clamp(-1.0, 1.0).acos() / PI;
+                sample_bilinear(map, u, v)
+            }
+        }
+    }
+}
+
+/// Samples `map` at texture coordinates in [0, 1], wrapping horizontally.
+fn sample_bilinear(map: &ColorBuffer, u: f32, v: f32) -> Vec3 {
+    let x = u * map.width as f32 - 0.5;
+    let y = (v * map.height as f32 - 0.5).clamp(0.0, (map.height - 1) as f32);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (x - x0, y - y0);
+
+    let wrap = |x: f32| (x as i64).rem_euclid(map.width as i64) as usize;
+    let (x0, x1) = (wrap(x0), wrap(x0 + 1.0));
+    let (y0, y1) = (y0 as usize, (y0 as usize + 1).min(map.height - 1));
+
+    let top = map.get(x0, y0).scale(1.0 - tx) + map.get(x1, y0).scale(tx);
+    let bottom = map.get(x0, y1).scale(1.0 - tx) + map.get(x1, y1).scale(tx);
+    top.scale(1.0 - ty) + bottom.scale(ty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let dir = std::env::temp_dir();
+        let paths =
+            ["a.pfm", "b.png", "c.pfm"].map(|name| dir.join(format!("environment_{}", name)));
+        let gray = ColorBuffer::new(2, 1, vec![Vec3::new(0.5, 0.5, 0.5); 2]);
+        gray.save(&paths[0]).unwrap();
+        gray.save(&paths[1]).unwrap();
+        std::fs::write(&paths[2], "PF\n0 2\n-1.0\n").unwrap();
+
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let linear = Environment::load(&paths[0]).unwrap().get_color(&up);
+        assert_eq!(linear, Vec3::new(0.5, 0.5, 0.5));
+        // The PNG holds sRGB display values.
+        let display = Environment::load(&paths[1]).unwrap().get_color(&up);
+        assert!((display.x() - srgb_decode(0.5)).abs() < 0.01);
+        let empty = Environment::load(&paths[2]);
+        assert_eq!(empty.err().unwrap().kind(), io::ErrorKind::InvalidData);
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
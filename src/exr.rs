@@ -0,0 +1,332 @@
+use std::io::{self, Write};
+
+use crate::deflate::zlib_compress;
+
+const MAGIC: [u8; 4] = [0x76, 0x2F, 0x31, 0x01];
+const LONG_NAMES_FLAG: u32 = 0x400;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExrPixelType {
+    Half,
+    Float,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExrCompression {
+    None,
+    /// Zlib over blocks of 16 scanlines.
+    Zip,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ExrOptions {
+    pub pixel_type: ExrPixelType,
+    pub compression: ExrCompression,
+    /// Adds the AOV layers of the image next to the beauty pass.
+    pub aovs: bool,
+}
+
+impl Default for ExrOptions {
+    fn default() -> Self {
+        Self {
+            pixel_type: ExrPixelType::Half,
+            compression: ExrCompression::Zip,
+            aovs: true,
+        }
+    }
+}
+
+/// Linear image channel, e.g. `R` of the beauty pass or `albedo.R` of a layer.
+pub struct ExrChannel {
+    pub name: String,
+    pub pixel_type: ExrPixelType,
+    /// Values of the pixels, rows top to bottom.
+    pub values: Vec<f32>,
+}
+
+impl ExrChannel {
+    pub fn new(name: &str, pixel_type: ExrPixelType, values: Vec<f32>) -> Self {
+        Self {
+            name: name.to_string(),
+            pixel_type,
+            values,
+        }
+    }
+}
+
+/// Writes `channels` as a single-part scanline OpenEXR image.
+pub fn write_exr<W: Write>(
+    mut out: W,
+    width: usize,
+    height: usize,
+    channels: &[ExrChannel],
+    compression: ExrCompression,
+) -> io::Result<()> {
+    let mut channels: Vec<&ExrChannel> = channels.iter().collect();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+    if channels
+        .iter()
+        .any(|channel| channel.values.len() != width * height)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "channel size doesn't match the image size",
+        ));
+    }
+
+    let (lines_per_block, compression_id) = match compression {
+        ExrCompression::None => (1, 0u8),
+        ExrCompression::Zip => (16, 3u8),
+    };
+
+    let mut header = vec![];
+    let mut chlist = vec![];
+    for channel in channels.iter() {
+        chlist.extend_from_slice(channel.name.as_bytes());
+        chlist.push(0);
+        let pixel_type: i32 = match channel.pixel_type {
+            ExrPixelType::Half => 1,
+            ExrPixelType::Float => 2,
+        };
+        chlist.extend_from_slice(&pixel_type.to_le_bytes());
+        // pLinear and reserved bytes, then x and y sampling.
+        chlist.extend_from_slice(&[0; 4]);
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+    }
+    chlist.push(0);
+    write_attribute(&mut header, "channels", "chlist", &chlist);
+    write_attribute(&mut header, "compression", "compression", &[compression_id]);
+
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    write_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_attribute(&mut header, "displayWindow", "box2i", &window);
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    let mut blocks = vec![];
+    for y0 in (0..height).step_by(lines_per_block) {
+        let mut raw = vec![];
+        for y in y0..(y0 + lines_per_block).min(height) {
+            for channel in channels.iter() {
+                for &value in &channel.values[y * width..(y + 1) * width] {
+                    match channel.pixel_type {
+                        ExrPixelType::Half => {
+                            raw.extend_from_slice(&f32_to_f16(value).to_le_bytes())
+                        }
+                        ExrPixelType::Float => raw.extend_from_slice(&value.to_le_bytes()),
+                    }
+                }
+            }
+        }
+        if compression == ExrCompression::Zip {
+            let compressed = zlib_compress(&predict(&interleave(&raw)));
+            // Readers treat blocks which didn't shrink as stored uncompressed.
+            if compressed.len() < raw.len() {
+                raw = compressed;
+            }
+        }
+        let mut block = (y0 as i32).to_le_bytes().to_vec();
+        block.extend_from_slice(&(raw.len() as i32).to_le_bytes());
+        block.extend_from_slice(&raw);
+        blocks.push(block);
+    }
+
+    let long_names = channels.iter().any(|channel| channel.name.len() > 31);
+    let version = 2 | if long_names { LONG_NAMES_FLAG } else { 0 };
+    out.write_all(&MAGIC)?;
+    out.write_all(&version.to_le_bytes())?;
+    out.write_all(&header)?;
+
+    let mut offset = (MAGIC.len() + 4 + header.len() + blocks.len() * 8) as u64;
+    for block in blocks.iter() {
+        out.write_all(&offset.to_le_bytes())?;
+        offset += block.len() as u64;
+    }
+    for block in blocks.iter() {
+        out.write_all(block)?;
+    }
+    out.flush()
+}
+
+/// Converts to the IEEE 754 half-precision bits, rounding to nearest even.
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xFF) as i32;
+    let mantissa = bits & 0x7F_FFFF;
+
+    if exp == 0xFF {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7C00 | nan;
+    }
+    let exp = exp - 127 + 15;
+    if exp >= 0x1F {
+        return sign | 0x7C00;
+    }
+    if exp <= 0 {
+        if exp < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exp) as u32;
+        let round_bit = 1 << (shift - 1);
+        let mut half = mantissa >> shift;
+        if mantissa & round_bit != 0 && mantissa & (3 * round_bit - 1) != 0 {
+            half += 1;
+        }
+        return sign | half as u16;
+    }
+
+    let mut half = ((exp as u32) << 10) | (mantissa >> 13);
+    let rest = mantissa & 0x1FFF;
+    if rest > 0x1000 || (rest == 0x1000 && half & 1 != 0) {
+        // A carry into the exponent correctly rounds up to the next power.
+        half += 1;
+    }
+    sign | half as u16
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// Moves the even bytes to the first half and the odd ones to the second.
+fn interleave(raw: &[u8]) -> Vec<u8> {
+    raw.iter()
+        .step_by(2)
+        .chain(raw.iter().skip(1).step_by(2))
+        .copied()
+        .collect()
+}
+
+/// Replaces the bytes by their differences, as the ZIP codec expects.
+fn predict(data: &[u8]) -> Vec<u8> {
+    let mut out = data.to_vec();
+    for i in 1..data.len() {
+        out[i] = data[i].wrapping_sub(data[i - 1]).wrapping_add(128);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::zlib_decompress;
+
+    #[test]
+    fn test_f32_to_f16() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(f32_to_f16(1.0), 0x3C00);
+        assert_eq!(f32_to_f16(0.5), 0x3800);
+        assert_eq!(f32_to_f16(-2.0), 0xC000);
+        assert_eq!(f32_to_f16(65504.0), 0x7BFF);
+        assert_eq!(f32_to_f16(1.0e6), 0x7C00);
+        assert_eq!(f32_to_f16(5.960_464_5e-8), 0x0001);
+        assert_eq!(f32_to_f16(6.097_555e-5), 0x03FF);
+        assert_eq!(f32_to_f16(1.0 + 1.0 / 2048.0), 0x3C00);
+        assert_eq!(f32_to_f16(1.0 + 3.0 / 2048.0), 0x3C02);
+        assert_eq!(f32_to_f16(f32::NAN) & 0x7C00, 0x7C00);
+    }
+
+    #[test]
+    fn test_write_exr() {
+        let channels = [
+            ExrChannel::new("R", ExrPixelType::Half, vec![1.0; 6]),
+            ExrChannel::new("G", ExrPixelType::Float, vec![0.5; 6]),
+        ];
+        let mut bytes = vec![];
+        write_exr(&mut bytes, 3, 2, &channels, ExrCompression::None).unwrap();
+        assert_eq!(bytes[..4], MAGIC);
+
+        // Two scanlines of 3 float G and 3 half R values after the offsets.
+        let block_len = 8 + 3 * 4 + 3 * 2;
+        let offset = u64::from_le_bytes(
+            bytes[bytes.len() - 2 * block_len - 16..][..8]
+                .try_into()
+                .unwrap(),
+        );
+        assert_eq!(offset as usize, bytes.len() - 2 * block_len);
+        let block = &bytes[offset as usize..][..block_len];
+        assert_eq!(block[8..12], 0.5f32.to_le_bytes());
+        assert_eq!(block[20..22], 0x3C00u16.to_le_bytes());
+    }
+
+    #[test]
+    fn test_write_exr_zip() {
+        let (width, height) = (4, 20);
+        let red: Vec<f32> = (0..width * height).map(|i| i as f32 / 8.0).collect();
+        let green: Vec<f32> = (0..width * height).map(|i| (i % 7) as f32).collect();
+        let channels = [
+            ExrChannel::new("R", ExrPixelType::Half, red.clone()),
+            ExrChannel::new("G", ExrPixelType::Float, green.clone()),
+        ];
+        let mut bytes = vec![];
+        write_exr(&mut bytes, width, height, &channels, ExrCompression::Zip).unwrap();
+
+        // Skip the attributes up to the empty name ending the header.
+        let mut pos = 8;
+        while bytes[pos] != 0 {
+            for _ in 0..2 {
+                pos += bytes[pos..].iter().position(|&b| b == 0).unwrap() + 1;
+            }
+            let size = i32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap());
+            pos += 4 + size as usize;
+        }
+        let offsets_pos = pos + 1;
+
+        // Blocks of 16 and 4 scanlines, each with the float G and half R values.
+        for (i, y0) in [0usize, 16].into_iter().enumerate() {
+            let offset = &bytes[offsets_pos + 8 * i..][..8];
+            let block = &bytes[u64::from_le_bytes(offset.try_into().unwrap()) as usize..];
+            assert_eq!(
+                i32::from_le_bytes(block[..4].try_into().unwrap()),
+                y0 as i32
+            );
+            let size = i32::from_le_bytes(block[4..8].try_into().unwrap()) as usize;
+
+            let mut expected = vec![];
+            for y in y0..(y0 + 16).min(height) {
+                for x in 0..width {
+                    expected.extend_from_slice(&green[y * width + x].to_le_bytes());
+                }
+                for x in 0..width {
+                    expected.extend_from_slice(&f32_to_f16(red[y * width + x]).to_le_bytes());
+                }
+            }
+            assert!(size < expected.len());
+
+            let mut data = zlib_decompress(&block[8..8 + size]).unwrap();
+            for j in 1..data.len() {
+                data[j] = data[j].wrapping_add(data[j - 1]).wrapping_sub(128);
+            }
+            let (even, odd) = data.split_at(data.len().div_ceil(2));
+            let raw: Vec<u8> = (0..data.len())
+                .map(|j| if j % 2 == 0 { even[j / 2] } else { odd[j / 2] })
+                .collect();
+            assert_eq!(raw, expected);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::vec3::Vec3;
+
+/// How the pixels combine their samples into the final radiance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Accumulation {
+    /// Plain mean of the samples.
+    #[default]
+    Mean,
+    /// Samples are spread over `n_buckets` by index and the pixel takes the
+    /// bucket mean of median luminance. A rare very bright sample only spoils
+    /// its own bucket, at the price of a slight darkening bias.
+    MedianOfMeans { n_buckets: usize },
+}
+
+impl Accumulation {
+    pub(crate) fn n_buckets(&self) -> usize {
+        match self {
+            Accumulation::Mean => 0,
+            Accumulation::MedianOfMeans { n_buckets } => *n_buckets,
+        }
+    }
+}
+
+/// Scales `radiance` down so that no component exceeds `max`, keeping its
+/// hue.
+pub fn clamp_radiance(radiance: Vec3, max: f32) -> Vec3 {
+    let peak = radiance.x().max(radiance.y()).max(radiance.z());
+    if peak > max {
+        radiance.scale(max / peak)
+    } else {
+        radiance
+    }
+}
+
+/// Median of the bucket `sums` of `n_samples` samples dealt round-robin,
+/// ordered by luminance. Empty buckets are ignored.
+pub(crate) fn median_of_means(sums: &[Vec3], n_samples: usize) -> Vec3 {
+    let n_buckets = sums.len();
+    let mut means: Vec<Vec3> = sums
+        .iter()
+        .enumerate()
+        .filter_map(|(i, sum)| {
+            let count = n_samples / n_buckets + usize::from(i < n_samples % n_buckets);
+            (count > 0).then(|| sum.scale(1.0 / count as f32))
+        })
+        .collect();
+    if means.is_empty() {
+        return Vec3::zeros();
+    }
+    means.sort_by(|a, b| luminance(a).total_cmp(&luminance(b)));
+    let mid = means.len() / 2;
+    if means.len().is_multiple_of(2) {
+        (means[mid - 1] + means[mid]).scale(0.5)
+    } else {
+        means[mid]
+    }
+}
+
+fn luminance(color: &Vec3) -> f32 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clamp_radiance() {
+        let clamped = clamp_radiance(Vec3::new(20.0, 10.0, 5.0), 4.0);
+        assert_eq!(clamped, Vec3::new(4.0, 2.0, 1.0));
+        let dim = Vec3::new(0.5, 0.25, 1.0);
+        assert_eq!(clamp_radiance(dim, 4.0), dim);
+    }
+
+    #[test]
+    fn test_median_of_means() {
+        let one = Vec3::new(1.0, 1.0, 1.0);
+        // 9 samples of 1 and one firefly of 1000 in bucket 0.
+        let mut sums = vec![one.scale(2.0); 5];
+        sums[0] = Vec3::new(1001.0, 1001.0, 1001.0);
+        assert_eq!(median_of_means(&sums, 10), one);
+        // Only the first 3 buckets hold samples.
+        let sums = [one, one.scale(3.0), one.scale(2.0), Vec3::zeros()];
+        assert_eq!(median_of_means(&sums, 3), one.scale(2.0));
+    }
+}
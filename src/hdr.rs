@@ -0,0 +1,268 @@
+use std::io::{self, BufRead, Write};
+
+use crate::{
+    buffer::{invalid_data, ColorBuffer},
+    vec3::Vec3,
+};
+
+const MIN_RLE_WIDTH: usize = 8;
+const MAX_RLE_WIDTH: usize = 0x7FFF;
+const MAX_RUN: usize = 127;
+const MAX_LITERAL: usize = 128;
+
+/// Writes linear `colors` as a Radiance RGBE image with run-length encoded
+/// scanlines. Rows go top to bottom.
+pub fn write_hdr<W: Write>(
+    mut out: W,
+    width: usize,
+    height: usize,
+    colors: &[Vec3],
+) -> io::Result<()> {
+    write!(
+        out,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        height, width
+    )?;
+
+    for row in colors.chunks(width.max(1)) {
+        let rgbe: Vec<[u8; 4]> = row.iter().map(to_rgbe).collect();
+        if !(MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width) {
+            for pixel in rgbe.iter() {
+                out.write_all(pixel)?;
+            }
+            continue;
+        }
+
+        out.write_all(&[2, 2, (width >> 8) as u8, (width & 0xFF) as u8])?;
+        for i in 0..4 {
+            let channel: Vec<u8> = rgbe.iter().map(|pixel| pixel[i]).collect();
+            write_rle(&mut out, &channel)?;
+        }
+    }
+    out.flush()
+}
+
+/// Reads a Radiance RGBE image, either flat or run-length encoded.
+pub fn read_hdr<R: BufRead>(mut inp: R) -> io::Result<ColorBuffer> {
+    let mut line = String::new();
+    inp.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid_data("not a Radiance HDR file"));
+    }
+    loop {
+        line.clear();
+        if inp.read_line(&mut line)? == 0 {
+            return Err(invalid_data("missing HDR resolution"));
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid_data("unsupported HDR pixel format"));
+        }
+    }
+
+    line.clear();
+    inp.read_line(&mut line)?;
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let (flip, height, width) = match tokens.as_slice() {
+        ["-Y", height, "+X", width] => (false, height, width),
+        ["+Y", height, "+X", width] => (true, height, width),
+        _ => return Err(invalid_data("unsupported HDR orientation")),
+    };
+    let height: usize = height.parse().map_err(|_| invalid_data("bad HDR height"))?;
+    let width: usize = width.parse().map_err(|_| invalid_data("bad HDR width"))?;
+
+    let mut rows = Vec::with_capacity(height);
+    for _ in 0..height {
+        let rgbe = read_scanline(&mut inp, width)?;
+        rows.push(rgbe.iter().map(from_rgbe).collect::<Vec<_>>());
+    }
+    if flip {
+        rows.reverse();
+    }
+    Ok(ColorBuffer::new(width, height, rows.concat()))
+}
+
+pub fn to_rgbe(color: &Vec3) -> [u8; 4] {
+    let max = color.x().max(color.y()).max(color.z());
+    if max <= 1.0e-32 || !max.is_finite() {
+        return [0; 4];
+    }
+    let mut exp = max.log2().floor() as i32 + 1;
+    if max / 2f32.powi(exp) >= 1.0 {
+        exp += 1;
+    }
+    // Values beyond the largest exponent saturate instead of wrapping to black.
+    let exp = exp.min(127);
+    let scale = 256.0 / 2f32.powi(exp);
+    [
+        (color.x().max(0.0) * scale) as u8,
+        (color.y().max(0.0) * scale) as u8,
+        (color.z().max(0.0) * scale) as u8,
+        (exp + 128) as u8,
+    ]
+}
+
+pub fn from_rgbe(rgbe: &[u8; 4]) -> Vec3 {
+    if rgbe[3] == 0 {
+        return Vec3::zeros();
+    }
+    let scale = 2f32.powi(rgbe[3] as i32 - (128 + 8));
+    Vec3::new(
+        (rgbe[0] as f32 + 0.5) * scale,
+        (rgbe[1] as f32 + 0.5) * scale,
+        (rgbe[2] as f32 + 0.5) * scale,
+    )
+}
+
+/// Encodes one channel of a scanline as runs of equal bytes (count + 128)
+/// and literal dumps (count up to 128).
+fn write_rle<W: Write>(out: &mut W, data: &[u8]) -> io::Result<()> {
+    let mut pos = 0;
+    while pos < data.len() {
+        let run = run_length(data, pos);
+        if run >= 3 {
+            out.write_all(&[(run + 128) as u8, data[pos]])?;
+            pos += run;
+            continue;
+        }
+        let mut end = pos;
+        while end < data.len() && end - pos < MAX_LITERAL && run_length(data, end) < 3 {
+            end += 1;
+        }
+        out.write_all(&[(end - pos) as u8])?;
+        out.write_all(&data[pos..end])?;
+        pos = end;
+    }
+    Ok(())
+}
+
+fn run_length(data: &[u8], pos: usize) -> usize {
+    data[pos..]
+        .iter()
+        .take(MAX_RUN)
+        .take_while(|&&byte| byte == data[pos])
+        .count()
+}
+
+fn read_scanline<R: BufRead>(inp: &mut R, width: usize) -> io::Result<Vec<[u8; 4]>> {
+    let mut first = [0; 4];
+    inp.read_exact(&mut first)?;
+    let is_rle = first[0] == 2
+        && first[1] == 2
+        && first[2] & 0x80 == 0
+        && (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width);
+    if !is_rle {
+        return read_flat_scanline(inp, width, first);
+    }
+    if ((first[2] as usize) << 8 | first[3] as usize) != width {
+        return Err(invalid_data("HDR scanline width mismatch"));
+    }
+
+    let mut scanline = vec![[0; 4]; width];
+    for i in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0];
+            inp.read_exact(&mut count)?;
+            let count = count[0] as usize;
+            if count > 128 {
+                let count = count - 128;
+                let mut value = [0];
+                inp.read_exact(&mut value)?;
+                if x + count > width {
+                    return Err(invalid_data("bad HDR run length"));
+                }
+                for pixel in scanline[x..x + count].iter_mut() {
+                    pixel[i] = value[0];
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(invalid_data("bad HDR run length"));
+                }
+                let mut values = vec![0; count];
+                inp.read_exact(&mut values)?;
+                for (pixel, value) in scanline[x..x + count].iter_mut().zip(values) {
+                    pixel[i] = value;
+                }
+                x += count;
+            }
+        }
+    }
+    Ok(scanline)
+}
+
+/// Reads uncompressed pixels, expanding the old-style `1 1 1 n` repeats.
+fn read_flat_scanline<R: BufRead>(
+    inp: &mut R,
+    width: usize,
+    first: [u8; 4],
+) -> io::Result<Vec<[u8; 4]>> {
+    let mut scanline: Vec<[u8; 4]> = Vec::with_capacity(width);
+    let mut pixel = first;
+    let mut shift = 0;
+    loop {
+        if pixel[0] == 1 && pixel[1] == 1 && pixel[2] == 1 {
+            let prev = *scanline
+                .last()
+                .ok_or_else(|| invalid_data("bad HDR repeat"))?;
+            let count = (pixel[3] as usize) << shift;
+            if scanline.len() + count > width {
+                return Err(invalid_data("bad HDR repeat"));
+            }
+            scanline.extend(std::iter::repeat_n(prev, count));
+            shift += 8;
+        } else {
+            scanline.push(pixel);
+            shift = 0;
+        }
+        if scanline.len() == width {
+            return Ok(scanline);
+        }
+        inp.read_exact(&mut pixel)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rgbe() {
+        for color in [
+            Vec3::new(1.0, 0.5, 0.25),
+            Vec3::new(1000.0, 3.0, 0.0),
+            Vec3::new(0.001, 0.002, 0.003),
+        ] {
+            let decoded = from_rgbe(&to_rgbe(&color));
+            let max = color.x().max(color.y()).max(color.z());
+            for i in 0..3 {
+                assert!((decoded[i] - color[i]).abs() <= max / 128.0);
+            }
+        }
+        assert_eq!(from_rgbe(&to_rgbe(&Vec3::zeros())), Vec3::zeros());
+        assert_eq!(to_rgbe(&Vec3::new(f32::MAX, 1.0, 0.0)), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_hdr_roundtrip() {
+        for width in [5, 40] {
+            let colors: Vec<Vec3> = (0..width * 3)
+                .map(|i| match i % 11 {
+                    0..=5 => Vec3::new(2.0, 1.0, 0.5),
+                    n => Vec3::new(n as f32, 0.1, 0.0),
+                })
+                .collect();
+            let mut bytes = vec![];
+            write_hdr(&mut bytes, width, 3, &colors).unwrap();
+            let buffer = read_hdr(&bytes[..]).unwrap();
+            assert_eq!((buffer.width, buffer.height), (width, 3));
+            for (a, b) in buffer.colors.iter().zip(colors.iter()) {
+                assert_eq!(to_rgbe(a), to_rgbe(b));
+            }
+        }
+    }
+}
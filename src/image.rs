@@ -1,123 +1,1211 @@
-use crossbeam::scope;
-use std::{fs::File, io::Write};
-
-use rand::Rng;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    time::Duration,
+};
 
 use crate::{
-    camera::Camera,
+    aov::{Aov, AovSamples},
+    buffer::{get_extension, unsupported_format, ColorBuffer},
+    camera::{Camera, CameraSample},
+    denoise::{denoise, DenoiseGuide, DenoiseSettings},
+    exr::{write_exr, ExrChannel, ExrOptions, ExrPixelType},
+    film::{clamp_radiance, median_of_means, Accumulation},
+    hdr::write_hdr,
+    png::{write_png, PngOptions},
+    ppm::{write_pfm, write_ppm, PpmFormat},
+    progress::{CancelToken, ProgressObserver, ProgressTracker},
     ray::Ray,
+    rnd,
     scatters::{scatter_dielectric, scatter_lambertian, scatter_metal},
-    surfaces::{Material, Surfaces},
+    surfaces::{HitRes, Material, Surfaces},
+    tiles::{for_each_tile, get_n_threads, split_tiles},
+    tonemap::ToneMapping,
     vec3::Vec3,
 };
 
+const TILE_SIZE: usize = 16;
+
+pub struct RenderSettings {
+    /// Number of worker threads, all available cores if `None`.
+    pub n_threads: Option<usize>,
+    /// Side of the square tiles the workers pull from the shared queue.
+    pub tile_size: usize,
+    /// Observer called from the worker threads after every finished tile.
+    pub on_progress: Option<ProgressObserver>,
+    /// Stops the render once cancelled, keeping the samples rendered so far.
+    pub cancel: CancelToken,
+    /// Denoises the image after every render call or progressive pass.
+    pub denoise: Option<DenoiseSettings>,
+    /// Camera rays leaving the scene add nothing to the pixel and zero to
+    /// its alpha instead of the environment color.
+    pub transparent_background: bool,
+    /// Clamps the radiance of every sample to this maximum component to keep
+    /// rare bright paths from producing fireflies. Biased, darkens caustics.
+    pub max_sample_radiance: Option<f32>,
+    /// Renders only the pixels inside this window of the image, keeping the
+    /// framing of the full image, e.g. to re-render a problem area and
+    /// `composite` it into a previous render.
+    pub region: Option<Region>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            n_threads: None,
+            tile_size: TILE_SIZE,
+            on_progress: None,
+            cancel: CancelToken::new(),
+            denoise: None,
+            transparent_background: false,
+            max_sample_radiance: None,
+            region: None,
+        }
+    }
+}
+
+/// Window of an image in pixels, rows top to bottom.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Region {
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+}
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RTCKPT04";
+
 pub struct Image {
     pixels: Vec<Pixel>,
     width: usize,
     height: usize,
+    seed: u64,
+    tone_mapping: ToneMapping,
+    accumulation: Accumulation,
+    denoised: Option<Vec<Vec3>>,
 }
 
 impl Image {
     pub fn new(width: usize, height: usize) -> Self {
+        Self::with_seed(width, height, 0)
+    }
+
+    /// Image whose samples are fully determined by `seed`, independently of
+    /// the number of threads and of how the render was split into calls.
+    pub fn with_seed(width: usize, height: usize, seed: u64) -> Self {
         let mut pixels = Vec::with_capacity(width * height);
+        let (du, dv) = (1.0 / width as f32, 1.0 / height as f32);
         for y in (0..height).rev() {
             for x in 0..width {
-                let (u, v) = (x as f32 / width as f32, y as f32 / height as f32);
-                pixels.push(Pixel::new(u, v))
+                pixels.push(Pixel::new(x as f32 * du, y as f32 * dv, du, dv))
             }
         }
         Image {
             pixels,
             width,
             height,
+            seed,
+            tone_mapping: ToneMapping::default(),
+            accumulation: Accumulation::Mean,
+            denoised: None,
         }
     }
 
-    pub fn render(&mut self, camera: &Camera, surfaces: &Surfaces, n_threads: usize) {
-        let chunk_size = self.height * self.width / n_threads;
-        scope(|s| {
-            for pixels in self.pixels.chunks_mut(chunk_size) {
-                s.spawn(|_| {
-                    for pixel in pixels.iter_mut() {
-                        pixel.render(camera, surfaces);
-                    }
-                });
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn tone_mapping(&self) -> &ToneMapping {
+        &self.tone_mapping
+    }
+
+    /// Sets the output pipeline used for display formats (PNG, PPM). Float
+    /// formats always store the raw linear radiance.
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.tone_mapping = tone_mapping;
+    }
+
+    pub fn accumulation(&self) -> Accumulation {
+        self.accumulation
+    }
+
+    /// Sets how the pixels combine their samples. Must be chosen before
+    /// rendering as the samples are stored accordingly.
+    pub fn set_accumulation(&mut self, accumulation: Accumulation) {
+        if self.pixels.iter().any(|pixel| pixel.n_samples > 0) {
+            panic!("Accumulation must be set before rendering!");
+        }
+        if accumulation == (Accumulation::MedianOfMeans { n_buckets: 0 }) {
+            panic!("Number of buckets must be positive!");
+        }
+        self.accumulation = accumulation;
+        for pixel in self.pixels.iter_mut() {
+            pixel.bucket_sums = vec![Vec3::zeros(); accumulation.n_buckets()];
+        }
+    }
+
+    /// Number of samples accumulated by every pixel so far. Pixels of a
+    /// cancelled pass may hold one more.
+    pub fn n_samples(&self) -> usize {
+        self.pixels
+            .iter()
+            .map(|pixel| pixel.n_samples)
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn render<C: Camera + ?Sized>(
+        &mut self,
+        camera: &C,
+        surfaces: &Surfaces,
+        settings: &RenderSettings,
+    ) {
+        let tracker = ProgressTracker::new(self.count_tiles(settings));
+        let n_samples = self.region_n_samples(settings) + N_AA_STEPS;
+        self.render_pass(camera, surfaces, settings, &tracker, n_samples);
+    }
+
+    /// Renders `n_passes` passes of one sample per pixel each, accumulating
+    /// them into the image. `on_pass` is called after every pass with the
+    /// image and the number of finished passes; returning `false` stops the
+    /// render early, as does cancelling `settings.cancel`. Returns the number
+    /// of passes actually finished. Every pass first tops up pixels left
+    /// behind by a stopped pass, so a resumed render matches an uninterrupted one.
+    pub fn render_progressive<C, F>(
+        &mut self,
+        camera: &C,
+        surfaces: &Surfaces,
+        settings: &RenderSettings,
+        n_passes: usize,
+        on_pass: F,
+    ) -> usize
+    where
+        C: Camera + ?Sized,
+        F: FnMut(&Image, usize) -> bool,
+    {
+        let tracker = ProgressTracker::new(self.count_tiles(settings) * n_passes);
+        self.render_passes(camera, surfaces, settings, &tracker, n_passes, on_pass)
+    }
+
+    /// Keeps adding one-sample passes like `render_progressive` until the
+    /// wall-clock `budget` runs out; the pass in flight at the deadline is
+    /// stopped. Returns the number of samples per pixel achieved.
+    pub fn render_for<C, F>(
+        &mut self,
+        camera: &C,
+        surfaces: &Surfaces,
+        settings: &RenderSettings,
+        budget: Duration,
+        on_pass: F,
+    ) -> usize
+    where
+        C: Camera + ?Sized,
+        F: FnMut(&Image, usize) -> bool,
+    {
+        let tracker = ProgressTracker::with_budget(budget);
+        self.render_passes(camera, surfaces, settings, &tracker, usize::MAX, on_pass);
+        self.region_n_samples(settings)
+    }
+
+    fn render_passes<C, F>(
+        &mut self,
+        camera: &C,
+        surfaces: &Surfaces,
+        settings: &RenderSettings,
+        tracker: &ProgressTracker,
+        n_passes: usize,
+        mut on_pass: F,
+    ) -> usize
+    where
+        C: Camera + ?Sized,
+        F: FnMut(&Image, usize) -> bool,
+    {
+        let n_samples = self.region_n_samples(settings);
+        for pass in 1..=n_passes {
+            self.render_pass(camera, surfaces, settings, tracker, n_samples + pass);
+            if settings.cancel.is_cancelled() || tracker.is_expired() {
+                return pass - 1;
+            }
+            if !on_pass(self, pass) {
+                return pass;
+            }
+        }
+        n_passes
+    }
+
+    /// Window rendered with `settings`, the whole image by default.
+    fn region(&self, settings: &RenderSettings) -> Region {
+        let region = settings.region.unwrap_or(Region {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        });
+        self.check_region(&region);
+        region
+    }
+
+    fn check_region(&self, region: &Region) {
+        if region.width == 0 || region.height == 0 {
+            panic!("Region must not be empty!");
+        }
+        if region.x + region.width > self.width || region.y + region.height > self.height {
+            panic!("Region must be inside the image!");
+        }
+    }
+
+    /// Number of samples accumulated by every pixel of the rendered window.
+    fn region_n_samples(&self, settings: &RenderSettings) -> usize {
+        let region = self.region(settings);
+        (region.y..region.y + region.height)
+            .flat_map(|y| (region.x..region.x + region.width).map(move |x| (x, y)))
+            .map(|(x, y)| self.pixels[y * self.width + x].n_samples)
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Number of tiles overlapping the rendered window.
+    fn count_tiles(&self, settings: &RenderSettings) -> usize {
+        let region = self.region(settings);
+        let tile_size = settings.tile_size.max(1);
+        let n_cols = (region.x + region.width).div_ceil(tile_size) - region.x / tile_size;
+        let n_rows = (region.y + region.height).div_ceil(tile_size) - region.y / tile_size;
+        n_cols * n_rows
+    }
+
+    fn render_pass<C: Camera + ?Sized>(
+        &mut self,
+        camera: &C,
+        surfaces: &Surfaces,
+        settings: &RenderSettings,
+        tracker: &ProgressTracker,
+        n_samples: usize,
+    ) {
+        let (width, seed) = (self.width, self.seed);
+        let region = self.region(settings);
+        let n_threads = get_n_threads(settings.n_threads);
+        let mut tiles = split_tiles(&mut self.pixels, self.width, settings.tile_size);
+        tiles.retain(|tile| {
+            tile.x < region.x + region.width
+                && region.x < tile.x + tile.width
+                && tile.y < region.y + region.height
+                && region.y < tile.y + tile.height
+        });
+        for_each_tile(tiles, n_threads, |mut tile| {
+            if settings.cancel.is_cancelled() || tracker.is_expired() {
+                return;
             }
-        })
-        .unwrap();
+            let mut n_rendered = 0;
+            for (x, y, pixel) in tile.iter_mut() {
+                if !region.contains(x, y) {
+                    continue;
+                }
+                let pixel_seed = rnd::mix_seed(seed, (y * width + x) as u64);
+                n_rendered += pixel.render(camera, surfaces, settings, n_samples, pixel_seed);
+            }
+            let progress = tracker.add_tile(n_rendered);
+            if let Some(on_progress) = &settings.on_progress {
+                on_progress(&progress);
+            }
+        });
+
+        self.denoised = settings.denoise.map(|denoise| self.denoise(&denoise));
+    }
+
+    fn denoise(&self, settings: &DenoiseSettings) -> Vec<Vec3> {
+        let albedo = self.aov(Aov::Albedo).colors;
+        let normal = self.aov(Aov::Normal).colors;
+        let depth: Vec<f32> = self.aov(Aov::Depth).colors.iter().map(|d| d.x()).collect();
+        let has_samples: Vec<bool> = self
+            .pixels
+            .iter()
+            .map(|pixel| pixel.n_samples > 0)
+            .collect();
+        let guide = DenoiseGuide {
+            albedo: &albedo,
+            normal: &normal,
+            depth: &depth,
+            has_samples: &has_samples,
+        };
+        denoise(
+            self.width,
+            self.height,
+            &self.raw_radiance(),
+            &guide,
+            settings,
+        )
     }
 
-    pub fn to_ppm(&self, file_path: String) {
-        let mut out_file = File::create(file_path).unwrap();
+    /// Replaces the pixels of `region` with those of `other`, an image of the
+    /// same size rendered with `RenderSettings::region`, e.g. after fixing
+    /// the scene in that area. The denoised radiance is kept only if both
+    /// images have one.
+    pub fn composite(&mut self, other: &Image, region: Region) {
+        if other.width != self.width || other.height != self.height {
+            panic!("Images must have the same size!");
+        }
+        if other.accumulation != self.accumulation {
+            panic!("Images must have the same accumulation!");
+        }
+        self.check_region(&region);
+        for y in region.y..region.y + region.height {
+            let row = y * self.width;
+            let (start, end) = (row + region.x, row + region.x + region.width);
+            self.pixels[start..end].clone_from_slice(&other.pixels[start..end]);
+            if let (Some(denoised), Some(other)) = (&mut self.denoised, &other.denoised) {
+                denoised[start..end].copy_from_slice(&other[start..end]);
+            }
+        }
+        if other.denoised.is_none() {
+            self.denoised = None;
+        }
+    }
 
-        out_file
-            .write(format!("P3\n{} {}\n255\n", self.width, self.height).as_bytes())
-            .unwrap();
+    /// Writes the accumulated samples, AOVs, sample counters and seed to
+    /// `file_path`. The file is replaced atomically, so a crash during the
+    /// write leaves the previous checkpoint intact.
+    pub fn save_checkpoint<P: AsRef<Path>>(&self, file_path: P) -> io::Result<()> {
+        let file_path = file_path.as_ref();
+        let tmp_path = file_path.with_extension("tmp");
+        let mut out = BufWriter::new(File::create(&tmp_path)?);
 
+        out.write_all(CHECKPOINT_MAGIC)?;
+        out.write_all(&(self.width as u64).to_le_bytes())?;
+        out.write_all(&(self.height as u64).to_le_bytes())?;
+        out.write_all(&self.seed.to_le_bytes())?;
+        out.write_all(&(self.accumulation.n_buckets() as u64).to_le_bytes())?;
         for pixel in self.pixels.iter() {
-            let color = pixel.color.unwrap();
-            out_file
-                .write(format!("{} {} {}\n", color.r(), color.g(), color.b()).as_bytes())
-                .unwrap();
+            pixel.write_checkpoint(&mut out)?;
+        }
+        out.into_inner()?.sync_all()?;
+
+        fs::rename(tmp_path, file_path)
+    }
+
+    /// Restores an image saved with `save_checkpoint`. Continue it with
+    /// `render_progressive` for the passes left, i.e. the total minus
+    /// `n_samples()`.
+    pub fn load_checkpoint<P: AsRef<Path>>(file_path: P) -> io::Result<Self> {
+        let mut inp = BufReader::new(File::open(file_path)?);
+
+        let mut magic = [0; 8];
+        inp.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a render checkpoint",
+            ));
+        }
+        let width = read_u64(&mut inp)? as usize;
+        let height = read_u64(&mut inp)? as usize;
+        let seed = read_u64(&mut inp)?;
+        let n_buckets = read_u64(&mut inp)? as usize;
+
+        let mut image = Self::with_seed(width, height, seed);
+        if n_buckets > 0 {
+            image.set_accumulation(Accumulation::MedianOfMeans { n_buckets });
+        }
+        for pixel in image.pixels.iter_mut() {
+            pixel.read_checkpoint(&mut inp)?;
+        }
+        Ok(image)
+    }
+
+    /// Display colors of the pixels in [0, 1] after the tone mapping
+    /// pipeline, rows top to bottom.
+    pub fn colors(&self) -> Vec<Vec3> {
+        self.radiance()
+            .into_iter()
+            .map(|radiance| self.tone_mapping.apply(radiance))
+            .collect()
+    }
+
+    /// Linear radiance of the pixels, rows top to bottom. Denoised if the
+    /// last render had `RenderSettings::denoise` set.
+    pub fn radiance(&self) -> Vec<Vec3> {
+        match &self.denoised {
+            Some(denoised) => denoised.clone(),
+            None => self.raw_radiance(),
+        }
+    }
+
+    /// Linear mean radiance of the samples accumulated by the pixels.
+    pub fn raw_radiance(&self) -> Vec<Vec3> {
+        self.pixels.iter().map(|pixel| pixel.radiance()).collect()
+    }
+
+    /// Coverage of the pixels by surfaces in [0, 1]; one everywhere unless
+    /// rendered with `RenderSettings::transparent_background`. The radiance
+    /// is premultiplied by it.
+    pub fn alphas(&self) -> Vec<f32> {
+        self.pixels.iter().map(|pixel| pixel.alpha()).collect()
+    }
+
+    pub fn has_transparency(&self) -> bool {
+        self.pixels.iter().any(|pixel| pixel.alpha() < 1.0)
+    }
+
+    /// Values of `aov` for all the pixels, see `Aov` for their meaning.
+    pub fn aov(&self, aov: Aov) -> ColorBuffer {
+        let colors = self
+            .pixels
+            .iter()
+            .map(|pixel| pixel.aovs.get(aov, pixel.n_samples))
+            .collect();
+        ColorBuffer::new(self.width, self.height, colors)
+    }
+
+    /// Pixel coordinates (`x` right, `y` down, pixel `(i, j)` covering
+    /// `[i, i + 1) x [j, j + 1)`) where `point` lands in this image seen by
+    /// `camera`, and its depth.
+    pub fn project<C: Camera + ?Sized>(&self, camera: &C, point: &Vec3) -> Option<(f32, f32, f32)> {
+        let (u, v, depth) = camera.project(point)?;
+        Some((u * self.width as f32, (1.0 - v) * self.height as f32, depth))
+    }
+
+    /// Closest surface under the center of pixel `x`, `y` (rows top to
+    /// bottom), or `None` for the background. `HitRes::object_id` identifies
+    /// the surface.
+    pub fn pick<C: Camera + ?Sized>(
+        &self,
+        camera: &C,
+        surfaces: &Surfaces,
+        x: usize,
+        y: usize,
+    ) -> Option<HitRes> {
+        if x >= self.width || y >= self.height {
+            panic!("Pixel must be inside the image!");
+        }
+        let pixel = &self.pixels[y * self.width + x];
+        let (u, v) = (pixel.u + 0.5 * pixel.du, pixel.v + 0.5 * pixel.dv);
+        let ray = camera.center_ray(&CameraSample::new(u, v))?;
+        surfaces.hit(&ray, 0.001, f32::MAX)
+    }
+
+    /// Saves the image in the format given by the file extension: `png`,
+    /// binary `ppm`, `pfm`, `exr` or `hdr`. PNG images get an alpha channel
+    /// if the image has transparent pixels.
+    pub fn save<P: AsRef<Path>>(&self, file_path: P) -> io::Result<()> {
+        let file_path = file_path.as_ref();
+        match get_extension(file_path).as_deref() {
+            Some("png") => {
+                let options = PngOptions {
+                    alpha: self.has_transparency(),
+                    ..Default::default()
+                };
+                self.to_png(file_path, &options)
+            }
+            Some("ppm") => self.to_ppm(file_path, PpmFormat::Binary),
+            Some("pfm") => self.to_pfm(file_path),
+            Some("exr") => self.to_exr(file_path, &ExrOptions::default()),
+            Some("hdr") => self.to_hdr(file_path),
+            _ => Err(unsupported_format(file_path)),
+        }
+    }
+
+    /// Writes the display colors as a PNG. With `options.alpha` they are
+    /// unpremultiplied, as PNG stores straight alpha.
+    pub fn to_png<P: AsRef<Path>>(&self, file_path: P, options: &PngOptions) -> io::Result<()> {
+        let out = BufWriter::new(File::create(file_path)?);
+        let alphas = self.alphas();
+        let colors = if options.alpha {
+            self.radiance()
+                .into_iter()
+                .zip(alphas.iter())
+                .map(|(radiance, &alpha)| {
+                    let radiance = if alpha > 0.0 {
+                        radiance.scale(1.0 / alpha)
+                    } else {
+                        Vec3::zeros()
+                    };
+                    self.tone_mapping.apply(radiance)
+                })
+                .collect()
+        } else {
+            self.colors()
+        };
+        write_png(out, self.width, self.height, &colors, &alphas, options)
+    }
+
+    pub fn to_ppm<P: AsRef<Path>>(&self, file_path: P, format: PpmFormat) -> io::Result<()> {
+        let out = BufWriter::new(File::create(file_path)?);
+        write_ppm(out, self.width, self.height, &self.colors(), format)
+    }
+
+    /// Writes the linear radiance as a float PFM.
+    pub fn to_pfm<P: AsRef<Path>>(&self, file_path: P) -> io::Result<()> {
+        let out = BufWriter::new(File::create(file_path)?);
+        write_pfm(out, self.width, self.height, &self.radiance())
+    }
+
+    /// Writes the linear radiance as a Radiance RGBE image.
+    pub fn to_hdr<P: AsRef<Path>>(&self, file_path: P) -> io::Result<()> {
+        let out = BufWriter::new(File::create(file_path)?);
+        write_hdr(out, self.width, self.height, &self.radiance())
+    }
+
+    /// Writes the linear radiance and alpha as the `R`, `G`, `B` and `A`
+    /// channels of an OpenEXR image, followed by the AOV layers if
+    /// `options.aovs` is set.
+    pub fn to_exr<P: AsRef<Path>>(&self, file_path: P, options: &ExrOptions) -> io::Result<()> {
+        let out = BufWriter::new(File::create(file_path)?);
+        let channels = self.exr_channels(options);
+        write_exr(out, self.width, self.height, &channels, options.compression)
+    }
+
+    fn exr_channels(&self, options: &ExrOptions) -> Vec<ExrChannel> {
+        let mut layers = vec![(
+            ["R", "G", "B"].as_slice(),
+            options.pixel_type,
+            self.radiance(),
+        )];
+        let alphas = self
+            .alphas()
+            .into_iter()
+            .map(|a| Vec3::new(a, a, a))
+            .collect();
+        layers.push((["A"].as_slice(), options.pixel_type, alphas));
+        if options.aovs {
+            for aov in Aov::ALL {
+                let pixel_type = if aov.is_id() {
+                    ExrPixelType::Float
+                } else {
+                    options.pixel_type
+                };
+                layers.push((aov.channel_names(), pixel_type, self.aov(aov).colors));
+            }
+        }
+
+        let mut channels = vec![];
+        for (names, pixel_type, colors) in layers {
+            for (i, name) in names.iter().enumerate() {
+                let values = colors.iter().map(|color| color[i]).collect();
+                channels.push(ExrChannel::new(name, pixel_type, values));
+            }
         }
+        channels
     }
 }
 
+#[derive(Clone)]
 struct Pixel {
+    /// Film coordinates of the lower left corner and size of the pixel.
     u: f32,
     v: f32,
-    color: Option<Vec3>,
+    du: f32,
+    dv: f32,
+    color_sum: Vec3,
+    alpha_sum: f32,
+    /// Color sums of the sample buckets for `Accumulation::MedianOfMeans`.
+    bucket_sums: Vec<Vec3>,
+    n_samples: usize,
+    aovs: AovSamples,
 }
 
 const N_AA_STEPS: usize = 100;
-const AA_STRENGTH: f32 = 0.002;
 
 impl Pixel {
-    pub fn new(u: f32, v: f32) -> Self {
-        Self { u, v, color: None }
+    pub fn new(u: f32, v: f32, du: f32, dv: f32) -> Self {
+        Self {
+            u,
+            v,
+            du,
+            dv,
+            color_sum: Vec3::zeros(),
+            alpha_sum: 0.0,
+            bucket_sums: vec![],
+            n_samples: 0,
+            aovs: AovSamples::new(),
+        }
     }
 
-    pub fn render(&mut self, camera: &Camera, surfaces: &Surfaces) {
-        let mut color = Vec3::zeros();
-        let mut rng = rand::thread_rng();
-        for _ in 0..N_AA_STEPS {
-            let v = self.v as f32 + AA_STRENGTH * rng.gen::<f32>();
-            let u = self.u as f32 + AA_STRENGTH * rng.gen::<f32>();
-            let ray = camera.get_ray(u, v);
-            color += get_color(&ray, &surfaces, 0);
+    /// Renders the samples missing up to `n_samples`, each seeded from
+    /// `seed` and its index. Returns the number of samples rendered.
+    pub fn render<C: Camera + ?Sized>(
+        &mut self,
+        camera: &C,
+        surfaces: &Surfaces,
+        settings: &RenderSettings,
+        n_samples: usize,
+        seed: u64,
+    ) -> usize {
+        let n_rendered = n_samples.saturating_sub(self.n_samples);
+        for i in self.n_samples..n_samples {
+            rnd::seed(rnd::mix_seed(seed, i as u64));
+            let v = self.v + self.dv * rnd::gen_f32();
+            let u = self.u + self.du * rnd::gen_f32();
+            let sample = CameraSample::new(u, v);
+            let (ray, is_blocked) = match camera.generate_ray(&sample) {
+                Some(ray) => (ray, false),
+                // A ray blocked by the lens adds no light, but the pixel keeps
+                // the coverage seen along the chief ray.
+                None => match camera.center_ray(&sample) {
+                    Some(ray) => (ray, true),
+                    None => {
+                        // Film outside the camera projection stays black and opaque.
+                        self.alpha_sum += 1.0;
+                        self.aovs.add(None, i);
+                        continue;
+                    }
+                },
+            };
+            let hit_res = surfaces.hit(&ray, 0.001, f32::MAX);
+            if hit_res.is_some() || !settings.transparent_background {
+                if !is_blocked {
+                    let mut color = get_hit_color(&ray, hit_res.as_ref(), surfaces, 0);
+                    if let Some(max) = settings.max_sample_radiance {
+                        color = clamp_radiance(color, max);
+                    }
+                    self.color_sum += color;
+                    if !self.bucket_sums.is_empty() {
+                        let n_buckets = self.bucket_sums.len();
+                        self.bucket_sums[i % n_buckets] += color;
+                    }
+                }
+                self.alpha_sum += 1.0;
+            }
+            self.aovs.add(hit_res.as_ref(), i);
         }
-        color = color.scale(1.0 / N_AA_STEPS as f32);
+        self.n_samples += n_rendered;
+        n_rendered
+    }
 
-        color = Vec3::new(color.x().sqrt(), color.y().sqrt(), color.z().sqrt());
-        color = color.scale(255.99);
-        self.color = Some(color);
+    pub fn radiance(&self) -> Vec3 {
+        if self.n_samples == 0 {
+            return Vec3::zeros();
+        }
+        if !self.bucket_sums.is_empty() {
+            return median_of_means(&self.bucket_sums, self.n_samples);
+        }
+        self.color_sum.scale(1.0 / self.n_samples as f32)
+    }
+
+    pub fn alpha(&self) -> f32 {
+        if self.n_samples == 0 {
+            return 1.0;
+        }
+        self.alpha_sum / self.n_samples as f32
+    }
+
+    fn write_checkpoint<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let aovs = &self.aovs;
+        for vec in [
+            self.color_sum,
+            aovs.albedo_sum,
+            aovs.normal_sum,
+            aovs.position_sum,
+        ] {
+            write_vec3(out, &vec)?;
+        }
+        for value in [self.alpha_sum, aovs.depth_sum] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        for count in [self.n_samples, aovs.n_hits] {
+            out.write_all(&(count as u64).to_le_bytes())?;
+        }
+        for id in [aovs.object_id, aovs.material_id] {
+            out.write_all(&id.map_or(u64::MAX, |id| id as u64).to_le_bytes())?;
+        }
+        for sum in self.bucket_sums.iter() {
+            write_vec3(out, sum)?;
+        }
+        Ok(())
+    }
+
+    fn read_checkpoint<R: Read>(&mut self, inp: &mut R) -> io::Result<()> {
+        self.color_sum = read_vec3(inp)?;
+        self.aovs.albedo_sum = read_vec3(inp)?;
+        self.aovs.normal_sum = read_vec3(inp)?;
+        self.aovs.position_sum = read_vec3(inp)?;
+        self.alpha_sum = f32::from_bits(read_u32(inp)?);
+        self.aovs.depth_sum = f32::from_bits(read_u32(inp)?);
+        self.n_samples = read_u64(inp)? as usize;
+        self.aovs.n_hits = read_u64(inp)? as usize;
+        let read_id = |inp: &mut R| {
+            let id = read_u64(inp)?;
+            io::Result::Ok((id != u64::MAX).then_some(id as usize))
+        };
+        self.aovs.object_id = read_id(inp)?;
+        self.aovs.material_id = read_id(inp)?;
+        for sum in self.bucket_sums.iter_mut() {
+            *sum = read_vec3(inp)?;
+        }
+        Ok(())
     }
 }
 
+fn write_vec3<W: Write>(out: &mut W, vec: &Vec3) -> io::Result<()> {
+    for i in 0..3 {
+        out.write_all(&vec[i].to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_vec3<R: Read>(inp: &mut R) -> io::Result<Vec3> {
+    let x = f32::from_bits(read_u32(inp)?);
+    let y = f32::from_bits(read_u32(inp)?);
+    let z = f32::from_bits(read_u32(inp)?);
+    Ok(Vec3::new(x, y, z))
+}
+
+fn read_u32<R: Read>(inp: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    inp.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(inp: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    inp.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
 fn get_color(ray: &Ray, surfaces: &Surfaces, depth: i32) -> Vec3 {
-    if let Some(hit_res) = surfaces.hit(ray, 0.001, f32::MAX) {
+    let hit_res = surfaces.hit(ray, 0.001, f32::MAX);
+    get_hit_color(ray, hit_res.as_ref(), surfaces, depth)
+}
+
+fn get_hit_color(ray: &Ray, hit_res: Option<&HitRes>, surfaces: &Surfaces, depth: i32) -> Vec3 {
+    if let Some(hit_res) = hit_res {
         let att: Vec3;
         if depth < 50 {
             if let Some(scattered) = match hit_res.material {
                 Material::Lambertian { attenuation } => {
                     att = attenuation;
-                    scatter_lambertian(&hit_res)
+                    scatter_lambertian(hit_res)
                 }
                 Material::Metal { attenuation, fuzz } => {
                     att = attenuation;
-                    scatter_metal(ray, &hit_res, fuzz)
+                    scatter_metal(ray, hit_res, fuzz)
                 }
                 Material::Dielectric { ref_k } => {
                     att = Vec3::ones();
-                    scatter_dielectric(ray, &hit_res, ref_k)
+                    scatter_dielectric(ray, hit_res, ref_k)
                 }
             } {
+                let scattered = scattered.with_time(ray.time);
                 return att * get_color(&scattered, surfaces, depth + 1);
             };
         }
-        return Vec3::zeros();
+        Vec3::zeros()
     } else {
-        let t = 0.5 * (ray.direction.y() + 1.0);
-        return Vec3::new(1.0, 1.0, 1.0).scale(1.0 - t) + Vec3::new(0.5, 0.7, 1.0).scale(t);
+        surfaces.environment().get_color(&ray.direction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        camera::PerspectiveCamera,
+        lens::{LensSystem, RealisticCamera},
+        surfaces::Sphere,
+    };
+
+    fn sky_scene(width: usize, height: usize) -> (PerspectiveCamera, Surfaces) {
+        let camera = PerspectiveCamera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            width as f32 / height as f32,
+            0.0,
+            1.0,
+        );
+        (camera, Surfaces::new(vec![]))
+    }
+
+    #[test]
+    fn test_render_progressive() {
+        let (camera, surfaces) = sky_scene(8, 4);
+        let mut image = Image::new(8, 4);
+        let mut passes = vec![];
+        let settings = RenderSettings {
+            n_threads: Some(2),
+            tile_size: 3,
+            ..Default::default()
+        };
+        let n_passes = image.render_progressive(&camera, &surfaces, &settings, 5, |image, pass| {
+            assert_eq!(image.n_samples(), pass);
+            passes.push(pass);
+            pass < 3
+        });
+        assert_eq!(n_passes, 3);
+        assert_eq!(passes, vec![1, 2, 3]);
+        assert_eq!(image.n_samples(), 3);
+        assert!(image.pixels.iter().all(|pixel| pixel.n_samples == 3));
+    }
+
+    #[test]
+    fn test_render_cancel() {
+        let (camera, surfaces) = sky_scene(8, 4);
+        let mut image = Image::new(8, 4);
+        let cancel = CancelToken::new();
+        let token = cancel.clone();
+        let settings = RenderSettings {
+            n_threads: Some(1),
+            tile_size: 2,
+            on_progress: Some(Box::new(move |progress| {
+                assert_eq!(progress.n_tiles, 8 * 4);
+                if progress.tiles_done == 10 {
+                    token.cancel();
+                }
+            })),
+            cancel,
+            ..Default::default()
+        };
+        let n_passes = image.render_progressive(&camera, &surfaces, &settings, 4, |_, _| true);
+        assert_eq!(n_passes, 1);
+        assert_eq!(image.n_samples(), 1);
+        let n_samples: usize = image.pixels.iter().map(|pixel| pixel.n_samples).sum();
+        assert_eq!(n_samples, 10 * 4);
+    }
+
+    #[test]
+    fn test_checkpoint_resume() {
+        let (camera, _) = sky_scene(8, 4);
+        let surfaces = Surfaces::generate_with_seed(7);
+        let settings = RenderSettings {
+            n_threads: Some(3),
+            tile_size: 3,
+            ..Default::default()
+        };
+
+        let accumulation = Accumulation::MedianOfMeans { n_buckets: 3 };
+        let mut full = Image::with_seed(8, 4, 11);
+        full.set_accumulation(accumulation);
+        full.render_progressive(&camera, &surfaces, &settings, 4, |_, _| true);
+
+        let path = std::env::temp_dir().join("rust_raytracer_test.ckpt");
+        let mut image = Image::with_seed(8, 4, 11);
+        image.set_accumulation(accumulation);
+        image.render_progressive(&camera, &surfaces, &settings, 2, |_, _| true);
+        image.save_checkpoint(&path).unwrap();
+        let mut image = Image::load_checkpoint(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(image.n_samples(), 2);
+        assert_eq!(image.accumulation(), accumulation);
+        image.render_progressive(&camera, &surfaces, &settings, 2, |_, _| true);
+
+        for (a, b) in image.pixels.iter().zip(full.pixels.iter()) {
+            assert_eq!(a.color_sum, b.color_sum);
+            assert_eq!(a.alpha_sum, b.alpha_sum);
+            assert_eq!(a.bucket_sums, b.bucket_sums);
+            assert_eq!(a.n_samples, b.n_samples);
+        }
+        assert_eq!(image.raw_radiance(), full.raw_radiance());
+        for aov in Aov::ALL {
+            assert_eq!(image.aov(aov).colors, full.aov(aov).colors);
+        }
+    }
+
+    #[test]
+    fn test_max_sample_radiance() {
+        let (camera, _) = sky_scene(8, 4);
+        let surfaces = Surfaces::generate_with_seed(7);
+        let settings = RenderSettings {
+            max_sample_radiance: Some(0.5),
+            ..Default::default()
+        };
+        let mut image = Image::new(8, 4);
+        image.render_progressive(&camera, &surfaces, &settings, 4, |_, _| true);
+        for radiance in image.raw_radiance() {
+            assert!((0..3).all(|i| radiance[i] <= 0.5 + 1e-6));
+        }
+    }
+
+    #[test]
+    fn test_aovs() {
+        let (camera, _) = sky_scene(45, 25);
+        let surfaces = Surfaces::new(vec![Box::new(Sphere::new(
+            Vec3::new(0.0, 0.0, -3.0),
+            1.0,
+            Material::Metal {
+                attenuation: Vec3::new(0.8, 0.6, 0.2),
+                fuzz: 0.0,
+            },
+        ))]);
+        let mut image = Image::new(45, 25);
+        image.render(&camera, &surfaces, &RenderSettings::default());
+
+        // The center pixel sees the sphere, the corner one the sky.
+        let (center, corner) = (12 * 45 + 22, 0);
+        let albedo = image.aov(Aov::Albedo).colors;
+        assert!((albedo[center] - Vec3::new(0.8, 0.6, 0.2)).length() < 1.0e-5);
+        assert_eq!(albedo[corner], Vec3::zeros());
+        let depth = image.aov(Aov::Depth).colors;
+        assert!((depth[center].x() - 2.0).abs() < 0.01);
+        assert_eq!(depth[corner].x(), f32::INFINITY);
+        let normal = image.aov(Aov::Normal).colors[center];
+        assert!(normal.z() > 0.99);
+        let position = image.aov(Aov::Position).colors[center];
+        assert!((position - Vec3::new(0.0, 0.0, -2.0)).length() < 0.01);
+        assert_eq!(image.aov(Aov::ObjectId).colors[center].x(), 0.0);
+        assert_eq!(image.aov(Aov::MaterialId).colors[center].x(), 1.0);
+        assert_eq!(image.aov(Aov::ObjectId).colors[corner].x(), -1.0);
+    }
+
+    #[test]
+    fn test_project_and_pick() {
+        let (camera, _) = sky_scene(9, 5);
+        let surfaces = Surfaces::new(vec![
+            Box::new(Sphere::new(
+                Vec3::new(0.0, 0.0, -3.0),
+                1.0,
+                Material::Lambertian {
+                    attenuation: Vec3::ones(),
+                },
+            )),
+            Box::new(Sphere::new(
+                Vec3::new(-5.0, 2.0, -3.0),
+                1.2,
+                Material::Lambertian {
+                    attenuation: Vec3::ones(),
+                },
+            )),
+        ]);
+        let image = Image::new(9, 5);
+
+        let (x, y, depth) = image.project(&camera, &Vec3::new(-5.0, 2.0, -3.0)).unwrap();
+        assert_eq!((x as usize, y as usize), (0, 0));
+        assert!((depth - 3.0).abs() < 1e-5);
+        assert!(image.project(&camera, &Vec3::new(0.0, 0.0, 3.0)).is_none());
+
+        assert_eq!(image.pick(&camera, &surfaces, 4, 2).unwrap().object_id, 0);
+        assert_eq!(image.pick(&camera, &surfaces, 0, 0).unwrap().object_id, 1);
+        assert!(image.pick(&camera, &surfaces, 8, 4).is_none());
+    }
+
+    #[test]
+    fn test_pick_through_lens() {
+        let lens = LensSystem::parse(include_str!("../lenses/dgauss50.txt")).unwrap();
+        let camera = RealisticCamera::new(
+            Vec3::zeros(),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            &lens,
+            1.0,
+            43.27,
+            4.0,
+        );
+        let surfaces = Surfaces::new(vec![Box::new(Sphere::new(
+            Vec3::new(0.0, 0.0, -4.0),
+            2.0,
+            Material::Lambertian {
+                attenuation: Vec3::ones(),
+            },
+        ))]);
+        let image = Image::new(20, 20);
+        // The corners of the film are vignetted, yet every pixel hits.
+        rnd::seed(1);
+        let expected = rnd::gen_f32();
+        rnd::seed(1);
+        for y in 0..20 {
+            for x in 0..20 {
+                assert!(image.pick(&camera, &surfaces, x, y).is_some());
+            }
+        }
+        assert_eq!(rnd::gen_f32(), expected);
+    }
+
+    #[test]
+    fn test_cat_eye_transparency() {
+        let mut camera = PerspectiveCamera::new(
+            Vec3::zeros(),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            2.0,
+            0.5,
+            10.0,
+        );
+        camera.set_cat_eye(1.5);
+        let settings = RenderSettings {
+            transparent_background: true,
+            ..Default::default()
+        };
+        // Vignetting darkens but doesn't change the coverage.
+        let mut sky = Image::new(8, 4);
+        sky.render_progressive(&camera, &Surfaces::new(vec![]), &settings, 8, |_, _| true);
+        assert!(sky.alphas().iter().all(|&alpha| alpha == 0.0));
+        let surfaces = Surfaces::new(vec![Box::new(Sphere::new(
+            Vec3::new(0.0, 0.0, -100.0),
+            95.0,
+            Material::Lambertian {
+                attenuation: Vec3::ones(),
+            },
+        ))]);
+        let mut image = Image::new(8, 4);
+        image.render_progressive(&camera, &surfaces, &settings, 8, |_, _| true);
+        assert!(image.alphas().iter().all(|&alpha| alpha == 1.0));
+    }
+
+    #[test]
+    fn test_transparent_background() {
+        let (camera, _) = sky_scene(9, 5);
+        let surfaces = Surfaces::new(vec![Box::new(Sphere::new(
+            Vec3::new(0.0, 0.0, -3.0),
+            1.0,
+            Material::Lambertian {
+                attenuation: Vec3::new(0.5, 0.5, 0.5),
+            },
+        ))]);
+        let mut image = Image::new(9, 5);
+        assert!(!image.has_transparency());
+        let settings = RenderSettings {
+            transparent_background: true,
+            ..Default::default()
+        };
+        image.render_progressive(&camera, &surfaces, &settings, 8, |_, _| true);
+
+        let (center, corner) = (2 * 9 + 4, 0);
+        let alphas = image.alphas();
+        assert_eq!(alphas[center], 1.0);
+        assert_eq!(alphas[corner], 0.0);
+        assert_eq!(image.radiance()[corner], Vec3::zeros());
+        // The sky still lights the sphere.
+        assert!(image.radiance()[center].x() > 0.0);
+        assert!(image.has_transparency());
+    }
+
+    #[test]
+    fn test_render_for() {
+        let (camera, surfaces) = sky_scene(8, 4);
+        let mut image = Image::new(8, 4);
+        let settings = RenderSettings::default();
+        let mut n_passes = 0;
+        let n_samples = image.render_for(
+            &camera,
+            &surfaces,
+            &settings,
+            Duration::from_millis(50),
+            |_, pass| {
+                n_passes = pass;
+                true
+            },
+        );
+        assert!(n_samples > 0);
+        assert_eq!(n_samples, image.n_samples());
+        assert!(n_passes == n_samples || n_passes + 1 == n_samples);
+    }
+
+    #[test]
+    fn test_render_region() {
+        let (camera, _) = sky_scene(8, 4);
+        let surfaces = Surfaces::generate_with_seed(7);
+        let mut full = Image::with_seed(8, 4, 3);
+        full.render_progressive(&camera, &surfaces, &Default::default(), 2, |_, _| true);
+
+        let region = Region {
+            x: 2,
+            y: 1,
+            width: 5,
+            height: 2,
+        };
+        let settings = RenderSettings {
+            tile_size: 3,
+            region: Some(region),
+            ..Default::default()
+        };
+        let mut patch = Image::with_seed(8, 4, 3);
+        let n_passes = patch.render_progressive(&camera, &surfaces, &settings, 2, |image, pass| {
+            assert_eq!(image.region_n_samples(&settings), pass);
+            true
+        });
+        assert_eq!(n_passes, 2);
+        assert_eq!(patch.count_tiles(&settings), 3);
+        for y in 0..4 {
+            for x in 0..8 {
+                let (a, b) = (&patch.pixels[y * 8 + x], &full.pixels[y * 8 + x]);
+                if region.contains(x, y) {
+                    assert_eq!(a.color_sum, b.color_sum);
+                    assert_eq!(a.n_samples, 2);
+                } else {
+                    assert_eq!(a.n_samples, 0);
+                }
+            }
+        }
+
+        let mut image = Image::with_seed(8, 4, 5);
+        image.render_progressive(&camera, &surfaces, &Default::default(), 2, |_, _| true);
+        let before = image.raw_radiance();
+        image.composite(&patch, region);
+        let (after, expected) = (image.raw_radiance(), full.raw_radiance());
+        for i in 0..8 * 4 {
+            let (x, y) = (i % 8, i / 8);
+            assert_eq!(
+                after[i],
+                if region.contains(x, y) {
+                    expected[i]
+                } else {
+                    before[i]
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_render_region_denoise() {
+        let (camera, _) = sky_scene(32, 16);
+        // The sphere crosses the left border of the region.
+        let surfaces = Surfaces::new(vec![Box::new(Sphere::new(
+            Vec3::new(-2.5, 0.0, -3.0),
+            1.0,
+            Material::Lambertian {
+                attenuation: Vec3::new(0.8, 0.3, 0.3),
+            },
+        ))]);
+        let mut settings = RenderSettings {
+            denoise: Some(DenoiseSettings::default()),
+            ..Default::default()
+        };
+        let mut full = Image::with_seed(32, 16, 3);
+        full.render_progressive(&camera, &surfaces, &settings, 4, |_, _| true);
+        let mut image = Image::with_seed(32, 16, 5);
+        image.render_progressive(&camera, &surfaces, &settings, 4, |_, _| true);
+        let before = image.radiance();
+
+        let region = Region {
+            x: 8,
+            y: 4,
+            width: 16,
+            height: 8,
+        };
+        let albedo = full.aov(Aov::Albedo).colors;
+        let row = 8 * 32;
+        assert_ne!(albedo[row + region.x - 1], Vec3::zeros());
+        assert_ne!(albedo[row + region.x], Vec3::zeros());
+        settings.region = Some(region);
+        let mut patch = Image::with_seed(32, 16, 3);
+        patch.render_progressive(&camera, &surfaces, &settings, 4, |_, _| true);
+        image.composite(&patch, region);
+
+        let (expected, radiance) = (full.radiance(), image.radiance());
+        for y in 0..16 {
+            for x in 0..32 {
+                let i = y * 32 + x;
+                if region.contains(x, y) {
+                    assert!(radiance[i].length().is_finite());
+                    assert!((radiance[i] - expected[i]).length() < 0.1);
+                } else {
+                    assert_eq!(radiance[i], before[i]);
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,510 @@
+use std::{f32::consts::PI, fs, io, path::Path};
+
+use crate::{
+    camera::{look_at_basis, Camera, CameraSample},
+    ray::Ray,
+    rnd,
+    vec3::Vec3,
+};
+
+/// One refracting surface (or the aperture stop) of a lens prescription,
+/// lengths in millimeters. Elements are listed from the scene side to the
+/// film side.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LensElement {
+    /// Radius of curvature, positive when the center of the sphere lies
+    /// towards the film; zero for the aperture stop.
+    pub radius: f32,
+    /// Distance along the axis to the next surface, or to the film for the
+    /// last one (replaced when focusing).
+    pub thickness: f32,
+    /// Index of refraction of the medium behind the surface, zero or one for
+    /// air.
+    pub ior: f32,
+    /// Clear aperture diameter.
+    pub aperture: f32,
+}
+
+impl LensElement {
+    pub fn is_stop(&self) -> bool {
+        self.radius == 0.0
+    }
+}
+
+/// Lens prescription as used in lens design tables.
+#[derive(Clone, Debug)]
+pub struct LensSystem {
+    elements: Vec<LensElement>,
+}
+
+impl LensSystem {
+    pub fn new(elements: Vec<LensElement>) -> Self {
+        if elements.is_empty() {
+            panic!("lens must have elements!");
+        }
+        Self { elements }
+    }
+
+    /// Parses a table with one element per line as four whitespace separated
+    /// columns: radius, thickness, IOR and aperture diameter, all lengths in
+    /// millimeters. Blank lines and `#` comments are skipped.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut elements = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |message| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", i + 1, message),
+                )
+            };
+            let values = line
+                .split_whitespace()
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid("invalid number"))?;
+            let [radius, thickness, ior, aperture] = values[..] else {
+                return Err(invalid("expected radius, thickness, ior and aperture"));
+            };
+            if thickness < 0.0 || ior < 0.0 || aperture <= 0.0 {
+                return Err(invalid("negative thickness, ior or aperture"));
+            }
+            elements.push(LensElement {
+                radius,
+                thickness,
+                ior,
+                aperture,
+            });
+        }
+        if elements.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "lens table has no elements",
+            ));
+        }
+        Ok(Self { elements })
+    }
+
+    pub fn load<P: AsRef<Path>>(file_path: P) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(file_path)?)
+    }
+
+    pub fn elements(&self) -> &[LensElement] {
+        &self.elements
+    }
+
+    /// Stops the lens down to an aperture stop `diameter` in millimeters, at
+    /// most the open aperture of the prescription.
+    pub fn set_aperture(&mut self, diameter: f32) {
+        let Some(stop) = self.elements.iter_mut().find(|element| element.is_stop()) else {
+            panic!("lens has no aperture stop!");
+        };
+        if diameter <= 0.0 || diameter > stop.aperture {
+            panic!("diameter must be positive and at most the open aperture!");
+        }
+        stop.aperture = diameter;
+    }
+}
+
+/// Lens surface in meters.
+#[derive(Clone, Copy)]
+struct Interface {
+    radius: f32,
+    thickness: f32,
+    ior: f32,
+    aperture_radius: f32,
+}
+
+/// Camera tracing the rays from the film through every surface of a
+/// `LensSystem`, with the vignetting, distortion and focus breathing of the
+/// real lens. Scene units are meters.
+///
+/// Rays stopped inside the lens leave the film black, so the image is
+/// darker the more the lens is stopped down; `exposure_compensation` gives
+/// the exposure bringing the center of the image back to the scene radiance.
+#[derive(Clone)]
+pub struct RealisticCamera {
+    origin: Vec3,
+    basis: (Vec3, Vec3, Vec3),
+    interfaces: Vec<Interface>,
+    film_width: f32,
+    film_height: f32,
+    pupil_radius: f32,
+    transmission: f32,
+}
+
+const PUPIL_GRID: usize = 32;
+const N_PUPIL_RADII: usize = 16;
+
+impl RealisticCamera {
+    /// Camera with the film at `lookfrom`, focused at `focus_dist` meters
+    /// in front of the film. `film_diagonal` is in millimeters, e.g. 43.27
+    /// for a full frame sensor.
+    pub fn new(
+        lookfrom: Vec3,
+        lookat: Vec3,
+        vup: Vec3,
+        lens: &LensSystem,
+        aspect: f32,
+        film_diagonal: f32,
+        focus_dist: f32,
+    ) -> Self {
+        let interfaces = lens
+            .elements
+            .iter()
+            .map(|element| Interface {
+                radius: element.radius * 0.001,
+                thickness: element.thickness * 0.001,
+                ior: element.ior,
+                aperture_radius: element.aperture * 0.0005,
+            })
+            .collect();
+        let film_height = film_diagonal * 0.001 / (1.0 + aspect * aspect).sqrt();
+        let mut camera = Self {
+            origin: lookfrom,
+            basis: look_at_basis(lookfrom, lookat, vup),
+            interfaces,
+            film_width: aspect * film_height,
+            film_height,
+            pupil_radius: 0.0,
+            transmission: 1.0,
+        };
+        camera.focus(focus_dist);
+        camera.bound_pupil();
+        camera
+    }
+
+    /// Exposure in stops compensating the light lost in the lens at the
+    /// center of the image, for `ToneMapping::exposure`.
+    pub fn exposure_compensation(&self) -> f32 {
+        -self.transmission.log2()
+    }
+
+    /// Effective focal length in meters.
+    pub fn focal_length(&self) -> f32 {
+        let (principal, focal) = self.scene_cardinal_points();
+        focal - principal
+    }
+
+    fn rear_z(&self) -> f32 {
+        self.interfaces.last().unwrap().thickness
+    }
+
+    fn front_z(&self) -> f32 {
+        self.interfaces
+            .iter()
+            .map(|interface| interface.thickness)
+            .sum()
+    }
+
+    /// Moves the film so that the plane `focus_dist` in front of it is in
+    /// focus, using the thick lens approximation of the system.
+    fn focus(&mut self, focus_dist: f32) {
+        let (principal_scene, focal_scene) = self.scene_cardinal_points();
+        let (principal_film, _) = self.film_cardinal_points();
+        let f = focal_scene - principal_scene;
+        let z = -focus_dist;
+        let c = (principal_film - z - principal_scene)
+            * (principal_film - z - 4.0 * f - principal_scene);
+        if c <= 0.0 {
+            panic!("focus_dist must be beyond the closest focus of the lens!");
+        }
+        let delta = 0.5 * (principal_film - z + principal_scene - c.sqrt());
+        let rear = self.interfaces.last_mut().unwrap();
+        rear.thickness += delta;
+    }
+
+    /// Principal plane and focal point of rays entering from the scene.
+    fn scene_cardinal_points(&self) -> (f32, f32) {
+        let x = 0.01 * self.interfaces[0].aperture_radius;
+        let origin = Vec3::new(x, 0.0, self.front_z() + 1.0);
+        let direction = Vec3::new(0.0, 0.0, -1.0);
+        let (out_origin, out_direction) = self
+            .trace_from_scene(origin, direction)
+            .expect("paraxial ray must pass the lens!");
+        cardinal_points(origin, out_origin, out_direction)
+    }
+
+    /// Principal plane and focal point of rays entering from the film.
+    fn film_cardinal_points(&self) -> (f32, f32) {
+        let x = 0.01 * self.interfaces.last().unwrap().aperture_radius;
+        let origin = Vec3::new(x, 0.0, self.rear_z() - 1.0);
+        let direction = Vec3::new(0.0, 0.0, 1.0);
+        let (out_origin, out_direction) = self
+            .trace_from_film(origin, direction)
+            .expect("paraxial ray must pass the lens!");
+        cardinal_points(origin, out_origin, out_direction)
+    }
+
+    /// Finds the disc on the rear element holding the exit pupil of every
+    /// film point, to sample only rays with a chance to pass the lens.
+    fn bound_pupil(&mut self) {
+        let rear_radius = self.interfaces.last().unwrap().aperture_radius;
+        let half_diagonal = 0.5 * self.film_width.hypot(self.film_height);
+        let cell = 2.0 * rear_radius / PUPIL_GRID as f32;
+        let (mut pupil_radius, mut n_center) = (0.0f32, 0);
+        for i in 0..N_PUPIL_RADII {
+            let film_point = Vec3::new(
+                half_diagonal * i as f32 / (N_PUPIL_RADII - 1) as f32,
+                0.0,
+                0.0,
+            );
+            for y in 0..PUPIL_GRID {
+                for x in 0..PUPIL_GRID {
+                    let rear_x = -rear_radius + (x as f32 + 0.5) * cell;
+                    let rear_y = -rear_radius + (y as f32 + 0.5) * cell;
+                    let rear_point = Vec3::new(rear_x, rear_y, self.rear_z());
+                    let direction = (rear_point - film_point).get_unit();
+                    if self.trace_from_film(film_point, direction).is_some() {
+                        pupil_radius = pupil_radius.max(rear_x.hypot(rear_y));
+                        n_center += usize::from(i == 0);
+                    }
+                }
+            }
+        }
+        if pupil_radius == 0.0 {
+            panic!("lens must let light through!");
+        }
+        self.pupil_radius = (pupil_radius + cell).min(rear_radius);
+        let pupil_area = PI * self.pupil_radius * self.pupil_radius;
+        self.transmission = (n_center as f32 * cell * cell / pupil_area).min(1.0);
+    }
+
+    /// Traces a camera space ray (film at z = 0, scene towards +z) leaving
+    /// the film through the lens. Returns the ray leaving the front element,
+    /// `None` if it's blocked or totally reflected.
+    fn trace_from_film(&self, origin: Vec3, direction: Vec3) -> Option<(Vec3, Vec3)> {
+        let (mut origin, mut direction) = (flip_z(origin), flip_z(direction));
+        let mut z = 0.0;
+        for (i, interface) in self.interfaces.iter().enumerate().rev() {
+            z -= interface.thickness;
+            origin = interface.intersect(z, origin, direction)?;
+            if interface.radius != 0.0 {
+                let eta_t = if i > 0 {
+                    medium_ior(self.interfaces[i - 1].ior)
+                } else {
+                    1.0
+                };
+                let normal = interface.normal(z, origin, direction);
+                direction = refract(-direction, normal, medium_ior(interface.ior) / eta_t)?;
+            }
+        }
+        Some((flip_z(origin), flip_z(direction)))
+    }
+
+    /// Traces a camera space ray entering the lens from the scene. Returns
+    /// the ray leaving the rear element towards the film.
+    fn trace_from_scene(&self, origin: Vec3, direction: Vec3) -> Option<(Vec3, Vec3)> {
+        let (mut origin, mut direction) = (flip_z(origin), flip_z(direction));
+        let mut z = -self.front_z();
+        for (i, interface) in self.interfaces.iter().enumerate() {
+            origin = interface.intersect(z, origin, direction)?;
+            if interface.radius != 0.0 {
+                let eta_i = if i > 0 {
+                    medium_ior(self.interfaces[i - 1].ior)
+                } else {
+                    1.0
+                };
+                let normal = interface.normal(z, origin, direction);
+                direction = refract(-direction, normal, eta_i / medium_ior(interface.ior))?;
+            }
+            z += interface.thickness;
+        }
+        Some((flip_z(origin), flip_z(direction)))
+    }
+
+    /// Ray leaving the film at `sample` through the point `x`, `y` of the
+    /// rear pupil.
+    fn pupil_ray(&self, sample: &CameraSample, x: f32, y: f32) -> Option<Ray> {
+        // The lens flips the image, so the film is read upside down.
+        let film_point = Vec3::new(
+            -(sample.u - 0.5) * self.film_width,
+            -(sample.v - 0.5) * self.film_height,
+            0.0,
+        );
+        let rear_point = Vec3::new(x, y, self.rear_z());
+        let direction = (rear_point - film_point).get_unit();
+        let (origin, direction) = self.trace_from_film(film_point, direction)?;
+        Some(Ray::new(
+            self.origin + self.to_world(origin),
+            self.to_world(direction).get_unit(),
+        ))
+    }
+
+    fn to_world(&self, camera_space: Vec3) -> Vec3 {
+        let (u, v, w) = self.basis;
+        u.scale(camera_space.x()) + v.scale(camera_space.y()) - w.scale(camera_space.z())
+    }
+}
+
+impl Camera for RealisticCamera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        let r = self.pupil_radius * rnd::gen_f32().sqrt();
+        let phi = 2.0 * PI * rnd::gen_f32();
+        self.pupil_ray(sample, r * phi.cos(), r * phi.sin())
+    }
+
+    /// Ray through the center of the pupil, or where vignetting blocks it,
+    /// the ray through the closest point of the pupil that gets through.
+    fn center_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        (0..=N_PUPIL_RADII).find_map(|ring| {
+            let r = self.pupil_radius * ring as f32 / N_PUPIL_RADII as f32;
+            let n_points = (6 * ring).max(1);
+            (0..n_points).find_map(|k| {
+                let phi = 2.0 * PI * k as f32 / n_points as f32;
+                self.pupil_ray(sample, r * phi.cos(), r * phi.sin())
+            })
+        })
+    }
+}
+
+impl Interface {
+    /// Intersects the lens space ray with the surface at `z` on the axis,
+    /// checking the clear aperture.
+    fn intersect(&self, z: f32, origin: Vec3, direction: Vec3) -> Option<Vec3> {
+        let t = if self.radius == 0.0 {
+            (z - origin.z()) / direction.z()
+        } else {
+            let center = Vec3::new(0.0, 0.0, z + self.radius);
+            let oc = origin - center;
+            let a = direction.dot(&direction);
+            let b = 2.0 * oc.dot(&direction);
+            let c = oc.dot(&oc) - self.radius * self.radius;
+            let discriminant = b * b - 4.0 * a * c;
+            if discriminant < 0.0 {
+                return None;
+            }
+            let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+            let (t0, t1) = (q / a, c / q);
+            let (t0, t1) = (t0.min(t1), t0.max(t1));
+            // The surface facing the ray is the closer hit for convex
+            // surfaces and the farther one for concave.
+            if (direction.z() > 0.0) ^ (self.radius < 0.0) {
+                t0
+            } else {
+                t1
+            }
+        };
+        if t.is_nan() || t <= 0.0 {
+            return None;
+        }
+        let point = origin + direction.scale(t);
+        let r2 = point.x() * point.x() + point.y() * point.y();
+        (r2 <= self.aperture_radius * self.aperture_radius).then_some(point)
+    }
+
+    /// Unit normal at `point` of the surface at `z`, facing the incoming ray.
+    fn normal(&self, z: f32, point: Vec3, direction: Vec3) -> Vec3 {
+        let normal = (point - Vec3::new(0.0, 0.0, z + self.radius)).get_unit();
+        if normal.dot(&direction) > 0.0 {
+            -normal
+        } else {
+            normal
+        }
+    }
+}
+
+/// Principal plane and focal point, as distances along the axis in lens
+/// space, of a ray entering parallel to the axis at `in_origin`.
+fn cardinal_points(in_origin: Vec3, out_origin: Vec3, out_direction: Vec3) -> (f32, f32) {
+    let t_focal = -out_origin.x() / out_direction.x();
+    let focal = -(out_origin + out_direction.scale(t_focal)).z();
+    let t_principal = (in_origin.x() - out_origin.x()) / out_direction.x();
+    let principal = -(out_origin + out_direction.scale(t_principal)).z();
+    (principal, focal)
+}
+
+/// Refracts the unit `incident` direction pointing away from the surface
+/// with the `normal` on its side.
+fn refract(incident: Vec3, normal: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = normal.dot(&incident);
+    let sin2_i = (1.0 - cos_i * cos_i).max(0.0);
+    let sin2_t = eta * eta * sin2_i;
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some((-incident).scale(eta) + normal.scale(eta * cos_i - cos_t))
+        .map(|direction| direction.get_unit())
+}
+
+fn medium_ior(ior: f32) -> f32 {
+    if ior == 0.0 {
+        1.0
+    } else {
+        ior
+    }
+}
+
+fn flip_z(vec: Vec3) -> Vec3 {
+    Vec3::new(vec.x(), vec.y(), -vec.z())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DGAUSS: &str = include_str!("../lenses/dgauss50.txt");
+
+    fn camera(lens: &LensSystem, focus_dist: f32) -> RealisticCamera {
+        RealisticCamera::new(
+            Vec3::zeros(),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            lens,
+            1.5,
+            43.27,
+            focus_dist,
+        )
+    }
+
+    #[test]
+    fn test_parse() {
+        let lens = LensSystem::parse(DGAUSS).unwrap();
+        assert_eq!(lens.elements().len(), 11);
+        assert!(lens.elements()[5].is_stop());
+        assert_eq!(
+            lens.elements()[0],
+            LensElement {
+                radius: 29.475,
+                thickness: 3.76,
+                ior: 1.67,
+                aperture: 25.2,
+            }
+        );
+        assert!(LensSystem::parse("1 2 3").is_err());
+        assert!(LensSystem::parse("# nothing").is_err());
+    }
+
+    #[test]
+    fn test_focus() {
+        let mut lens = LensSystem::parse(DGAUSS).unwrap();
+        lens.set_aperture(8.0);
+        let camera = camera(&lens, 2.0);
+        assert!((camera.focal_length() - 0.05).abs() < 0.002);
+        assert!(camera.exposure_compensation() > 0.0);
+
+        // Rays from the film center meet on the axis at the focus distance.
+        let mut n_passed = 0;
+        for i in 0..100 {
+            rnd::seed(i);
+            let Some(ray) = camera.generate_ray(&CameraSample::new(0.5, 0.5)) else {
+                continue;
+            };
+            let point = ray.get_point((-2.0 - ray.origin.z()) / ray.direction.z());
+            assert!(point.x().hypot(point.y()) < 1e-3, "{:?}", point);
+            n_passed += 1;
+        }
+        assert!(n_passed > 10);
+    }
+
+    #[test]
+    fn test_focus_breathing() {
+        // Focusing closer moves the lens away from the film.
+        let lens = LensSystem::parse(DGAUSS).unwrap();
+        assert!(camera(&lens, 0.5).rear_z() > camera(&lens, 10.0).rear_z());
+    }
+}
@@ -4,3 +4,25 @@ pub mod vec3;
 pub mod camera;
 pub mod scatters;
 pub mod image;
+pub mod tiles;
+pub mod progress;
+pub mod rnd;
+pub mod deflate;
+pub mod png;
+pub mod buffer;
+pub mod ppm;
+pub mod exr;
+pub mod hdr;
+pub mod environment;
+pub mod tonemap;
+pub mod aov;
+pub mod denoise;
+pub mod metrics;
+pub mod film;
+pub mod panorama;
+pub mod stereo;
+pub mod lens;
+pub mod aperture;
+pub mod physical;
+pub mod animation;
+pub mod video;
@@ -0,0 +1,366 @@
+use std::{fmt::Display, io, path::Path};
+
+use crate::{
+    buffer::{is_linear_format, ColorBuffer},
+    tonemap::{srgb_decode, ToneMapping},
+    vec3::Vec3,
+};
+
+const REL_MSE_EPS: f32 = 0.01;
+const SSIM_SIGMA: f32 = 1.5;
+const SSIM_C1: f32 = 0.01 * 0.01;
+const SSIM_C2: f32 = 0.03 * 0.03;
+const FLIP_SIGMA: f32 = 1.0;
+/// HyAB distance mapped to the maximal color error.
+const FLIP_MAX_COLOR_DIFF: f32 = 100.0;
+const FLIP_COLOR_EXPONENT: f32 = 0.7;
+const FLIP_FEATURE_EXPONENT: f32 = 0.5;
+
+/// Differences of an image against a reference. SSIM and FLIP expect
+/// display-referred values and clamp them to [0, 1].
+#[derive(Clone, Copy, Debug)]
+pub struct Metrics {
+    pub mse: f32,
+    pub rmse: f32,
+    /// MSE of every value relative to the squared reference value.
+    pub rel_mse: f32,
+    /// Peak signal-to-noise ratio in dB for a peak value of one.
+    pub psnr: f32,
+    pub ssim: f32,
+    /// Mean of the `flip_error_map`, in [0, 1].
+    pub flip: f32,
+}
+
+impl Display for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "MSE {:.6} RMSE {:.6} relMSE {:.6} PSNR {:.2}dB SSIM {:.4} FLIP {:.4}",
+            self.mse, self.rmse, self.rel_mse, self.psnr, self.ssim, self.flip
+        )
+    }
+}
+
+pub fn compare(image: &ColorBuffer, reference: &ColorBuffer) -> Metrics {
+    check_sizes(image, reference);
+    let n_values = (image.colors.len() * 3) as f32;
+    let mut mse = 0.0;
+    let mut rel_mse = 0.0;
+    for (a, b) in image.colors.iter().zip(reference.colors.iter()) {
+        for i in 0..3 {
+            let diff = (a[i] - b[i]) * (a[i] - b[i]);
+            mse += diff;
+            rel_mse += diff / (b[i] * b[i] + REL_MSE_EPS);
+        }
+    }
+    let mse = mse / n_values;
+    let flip = flip_error_map(image, reference);
+
+    Metrics {
+        mse,
+        rmse: mse.sqrt(),
+        rel_mse: rel_mse / n_values,
+        psnr: -10.0 * mse.log10(),
+        ssim: ssim(image, reference),
+        flip: flip.iter().sum::<f32>() / flip.len() as f32,
+    }
+}
+
+/// Loads the two images, compares them and optionally saves the false
+/// color difference image to `diff_path`. The images can be any format
+/// `ColorBuffer::load` reads, which excludes EXR. Linear images (PFM, HDR)
+/// go through `tone_mapping` first as the metrics expect display values;
+/// display images (PNG, PPM) are compared as they are. Both images must be
+/// of the same kind.
+pub fn compare_files<P: AsRef<Path>>(
+    image_path: P,
+    reference_path: P,
+    diff_path: Option<P>,
+    tone_mapping: &ToneMapping,
+) -> io::Result<Metrics> {
+    let linear = is_linear_format(image_path.as_ref());
+    if linear != is_linear_format(reference_path.as_ref()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "can't compare a display image with a linear one",
+        ));
+    }
+    let load = |file_path: P| -> io::Result<ColorBuffer> {
+        let mut buffer = ColorBuffer::load(file_path)?;
+        if linear {
+            for color in buffer.colors.iter_mut() {
+                *color = tone_mapping.apply(*color);
+            }
+        }
+        Ok(buffer)
+    };
+    let image = load(image_path)?;
+    let reference = load(reference_path)?;
+    if (image.width, image.height) != (reference.width, reference.height) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "images have different sizes",
+        ));
+    }
+    if let Some(diff_path) = diff_path {
+        difference_image(&image, &reference).save(diff_path)?;
+    }
+    Ok(compare(&image, &reference))
+}
+
+/// Mean structural similarity of the luminances, with Gaussian windows.
+pub fn ssim(image: &ColorBuffer, reference: &ColorBuffer) -> f32 {
+    check_sizes(image, reference);
+    let (width, height) = (image.width, image.height);
+    let x = luminances(image);
+    let y = luminances(reference);
+    let product =
+        |a: &[f32], b: &[f32]| -> Vec<f32> { a.iter().zip(b).map(|(a, b)| a * b).collect() };
+
+    let mu_x = blur(width, height, &x, SSIM_SIGMA);
+    let mu_y = blur(width, height, &y, SSIM_SIGMA);
+    let xx = blur(width, height, &product(&x, &x), SSIM_SIGMA);
+    let yy = blur(width, height, &product(&y, &y), SSIM_SIGMA);
+    let xy = blur(width, height, &product(&x, &y), SSIM_SIGMA);
+
+    let mut sum = 0.0;
+    for i in 0..x.len() {
+        let (mx, my) = (mu_x[i], mu_y[i]);
+        let sxx = xx[i] - mx * mx;
+        let syy = yy[i] - my * my;
+        let sxy = xy[i] - mx * my;
+        sum += ((2.0 * mx * my + SSIM_C1) * (2.0 * sxy + SSIM_C2))
+            / ((mx * mx + my * my + SSIM_C1) * (sxx + syy + SSIM_C2));
+    }
+    sum / x.len() as f32
+}
+
+/// Per-pixel perceptual error in [0, 1], modelled after LDR-FLIP: the
+/// HyAB color difference of the blurred images in L*a*b*, amplified where
+/// the edges of the images differ.
+pub fn flip_error_map(image: &ColorBuffer, reference: &ColorBuffer) -> Vec<f32> {
+    check_sizes(image, reference);
+    let (width, height) = (image.width, image.height);
+    let lab_a = blurred_lab(image);
+    let lab_b = blurred_lab(reference);
+    let edges_a = edges(width, height, &lab_a);
+    let edges_b = edges(width, height, &lab_b);
+
+    (0..lab_a.len())
+        .map(|i| {
+            let diff = lab_a[i] - lab_b[i];
+            let hyab = diff.x().abs() + (diff.y() * diff.y() + diff.z() * diff.z()).sqrt();
+            let color_error = (hyab / FLIP_MAX_COLOR_DIFF)
+                .min(1.0)
+                .powf(FLIP_COLOR_EXPONENT);
+            let feature_error = ((edges_a[i] - edges_b[i]).abs() / 2f32.sqrt())
+                .min(1.0)
+                .powf(FLIP_FEATURE_EXPONENT);
+            color_error.powf(1.0 - feature_error)
+        })
+        .collect()
+}
+
+/// FLIP error map of the images shown with the magma color map.
+pub fn difference_image(image: &ColorBuffer, reference: &ColorBuffer) -> ColorBuffer {
+    let colors = flip_error_map(image, reference)
+        .into_iter()
+        .map(magma)
+        .collect();
+    ColorBuffer::new(image.width, image.height, colors)
+}
+
+fn check_sizes(image: &ColorBuffer, reference: &ColorBuffer) {
+    if (image.width, image.height) != (reference.width, reference.height) {
+        panic!("images must have the same size!");
+    }
+}
+
+fn luminances(buffer: &ColorBuffer) -> Vec<f32> {
+    buffer
+        .colors
+        .iter()
+        .map(|c| {
+            0.2126 * c.x().clamp(0.0, 1.0)
+                + 0.7152 * c.y().clamp(0.0, 1.0)
+                + 0.0722 * c.z().clamp(0.0, 1.0)
+        })
+        .collect()
+}
+
+fn blurred_lab(buffer: &ColorBuffer) -> Vec<Vec3> {
+    let (width, height) = (buffer.width, buffer.height);
+    let lab: Vec<Vec3> = buffer.colors.iter().map(srgb_to_lab).collect();
+    let channels: Vec<Vec<f32>> = (0..3)
+        .map(|i| {
+            let values: Vec<f32> = lab.iter().map(|c| c[i]).collect();
+            blur(width, height, &values, FLIP_SIGMA)
+        })
+        .collect();
+    (0..lab.len())
+        .map(|i| Vec3::new(channels[0][i], channels[1][i], channels[2][i]))
+        .collect()
+}
+
+fn srgb_to_lab(color: &Vec3) -> Vec3 {
+    let r = srgb_decode(color.x().clamp(0.0, 1.0));
+    let g = srgb_decode(color.y().clamp(0.0, 1.0));
+    let b = srgb_decode(color.z().clamp(0.0, 1.0));
+    // Linear sRGB to XYZ relative to the D65 white point.
+    let x = (0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b) / 0.950_47;
+    let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b;
+    let z = (0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b) / 1.088_83;
+
+    let f = |t: f32| {
+        if t > 0.008_856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    Vec3::new(116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+/// Sobel gradient magnitude of the normalized lightness.
+fn edges(width: usize, height: usize, lab: &[Vec3]) -> Vec<f32> {
+    let at = |x: isize, y: isize| {
+        let x = x.clamp(0, width as isize - 1) as usize;
+        let y = y.clamp(0, height as isize - 1) as usize;
+        lab[y * width + x].x() / 100.0
+    };
+    let mut edges = Vec::with_capacity(lab.len());
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let gx = (at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x - 1, y)
+                - at(x - 1, y + 1))
+                / 4.0;
+            let gy = (at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x, y - 1)
+                - at(x + 1, y - 1))
+                / 4.0;
+            edges.push((gx * gx + gy * gy).sqrt());
+        }
+    }
+    edges
+}
+
+/// Separable Gaussian blur, clamping at the borders.
+fn blur(width: usize, height: usize, values: &[f32], sigma: f32) -> Vec<f32> {
+    let radius = (3.0 * sigma).ceil() as isize;
+    let kernel: Vec<f32> = (-radius..=radius)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let norm: f32 = kernel.iter().sum();
+
+    let pass = |values: &[f32], horizontal: bool| -> Vec<f32> {
+        let mut out = Vec::with_capacity(values.len());
+        for y in 0..height as isize {
+            for x in 0..width as isize {
+                let mut sum = 0.0;
+                for (k, weight) in kernel.iter().enumerate() {
+                    let offset = k as isize - radius;
+                    let (qx, qy) = if horizontal {
+                        ((x + offset).clamp(0, width as isize - 1), y)
+                    } else {
+                        (x, (y + offset).clamp(0, height as isize - 1))
+                    };
+                    sum += weight * values[qy as usize * width + qx as usize];
+                }
+                out.push(sum / norm);
+            }
+        }
+        out
+    };
+    pass(&pass(values, true), false)
+}
+
+/// Approximation of the magma color map for `t` in [0, 1].
+fn magma(t: f32) -> Vec3 {
+    let stops = [
+        Vec3::new(0.001, 0.000, 0.014),
+        Vec3::new(0.231, 0.060, 0.438),
+        Vec3::new(0.550, 0.161, 0.506),
+        Vec3::new(0.868, 0.288, 0.409),
+        Vec3::new(0.994, 0.624, 0.427),
+        Vec3::new(0.987, 0.991, 0.750),
+    ];
+    let t = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+    let i = (t.floor() as usize).min(stops.len() - 2);
+    let f = t - i as f32;
+    stops[i].scale(1.0 - f) + stops[i + 1].scale(f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tonemap::ToneMapper;
+
+    fn constant(value: f32) -> ColorBuffer {
+        ColorBuffer::new(8, 8, vec![Vec3::new(value, value, value); 64])
+    }
+
+    #[test]
+    fn test_identical() {
+        let colors = (0..64)
+            .map(|i| Vec3::new(i as f32 / 64.0, 0.5, 0.1))
+            .collect();
+        let image = ColorBuffer::new(8, 8, colors);
+        let metrics = compare(&image, &image);
+        assert_eq!(metrics.mse, 0.0);
+        assert_eq!(metrics.psnr, f32::INFINITY);
+        assert!((metrics.ssim - 1.0).abs() < 1.0e-4);
+        assert_eq!(metrics.flip, 0.0);
+    }
+
+    #[test]
+    fn test_constant_difference() {
+        let metrics = compare(&constant(0.6), &constant(0.5));
+        assert!((metrics.mse - 0.01).abs() < 1.0e-6);
+        assert!((metrics.rmse - 0.1).abs() < 1.0e-6);
+        assert!((metrics.rel_mse - 0.01 / 0.26).abs() < 1.0e-6);
+        assert!((metrics.psnr - 20.0).abs() < 1.0e-3);
+        assert!(metrics.ssim < 1.0);
+        assert!(metrics.flip > 0.0 && metrics.flip < 1.0);
+
+        let far = compare(&constant(1.0), &constant(0.0));
+        assert!(far.flip > metrics.flip);
+        assert!(far.ssim < metrics.ssim);
+    }
+
+    #[test]
+    fn test_compare_files() {
+        let dir = std::env::temp_dir();
+        let paths = ["a.pfm", "b.pfm", "c.png"].map(|name| dir.join(format!("metrics_{}", name)));
+        let colors: Vec<Vec3> = (0..64)
+            .map(|i| Vec3::new(i as f32 / 16.0, 2.0, 0.5))
+            .collect();
+        let brighter = colors.iter().map(|color| color.scale(1.05)).collect();
+        ColorBuffer::new(8, 8, colors).save(&paths[0]).unwrap();
+        ColorBuffer::new(8, 8, brighter).save(&paths[1]).unwrap();
+        constant(0.5).save(&paths[2]).unwrap();
+
+        let tone_mapping = ToneMapping {
+            tone_mapper: ToneMapper::Reinhard,
+            ..Default::default()
+        };
+        let metrics = compare_files(&paths[0], &paths[1], None, &tone_mapping).unwrap();
+        assert!(metrics.mse > 0.0 && metrics.mse < 0.01);
+        assert!(metrics.ssim > 0.9 && metrics.ssim < 1.0);
+        let mixed = compare_files(&paths[0], &paths[2], None, &tone_mapping);
+        assert_eq!(mixed.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_difference_image() {
+        let diff = difference_image(&constant(0.5), &constant(0.5));
+        assert_eq!((diff.width, diff.height), (8, 8));
+        assert_eq!(diff.colors[0], magma(0.0));
+    }
+}
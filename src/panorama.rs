@@ -0,0 +1,452 @@
+use std::f32::consts::PI;
+
+use crate::{
+    camera::{look_at_basis, on_film, Camera, CameraSample},
+    ray::Ray,
+    vec3::Vec3,
+};
+
+/// Full 360° x 180° latitude-longitude camera. Render it at a 2:1 aspect
+/// ratio; the center of the image looks at `lookat`, matching the layout
+/// read by `Environment::Map`.
+#[derive(Clone)]
+pub struct EquirectangularCamera {
+    origin: Vec3,
+    basis: (Vec3, Vec3, Vec3),
+}
+
+impl EquirectangularCamera {
+    pub fn new(lookfrom: Vec3, lookat: Vec3, vup: Vec3) -> Self {
+        Self {
+            origin: lookfrom,
+            basis: look_at_basis(lookfrom, lookat, vup),
+        }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        let phi = (sample.u - 0.5) * 2.0 * PI;
+        let theta = (sample.v - 0.5) * PI;
+        let local = Vec3::new(
+            theta.cos() * phi.sin(),
+            theta.sin(),
+            -theta.cos() * phi.cos(),
+        );
+        let direction = to_world(&self.basis, local);
+        Some(Ray::new(self.origin, direction))
+    }
+
+    fn center_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        self.generate_ray(sample)
+    }
+
+    /// `depth` is the distance from the camera.
+    fn project(&self, point: &Vec3) -> Option<(f32, f32, f32)> {
+        let (local, depth) = to_local(&self.basis, *point - self.origin)?;
+        let phi = local.x().atan2(-local.z());
+        let theta = local.y().clamp(-1.0, 1.0).asin();
+        on_film(0.5 + phi / (2.0 * PI), 0.5 + theta / PI, depth)
+    }
+}
+
+/// Mapping from the angle off the optical axis to the radius in the image
+/// circle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FisheyeProjection {
+    /// Radius proportional to the angle.
+    Equidistant,
+    /// Radius proportional to `sin(angle / 2)`, preserving solid angles.
+    Equisolid,
+}
+
+/// Circular fisheye camera; the image circle of `fov` degrees (up to 360)
+/// fits the image height and the film outside it stays black.
+#[derive(Clone)]
+pub struct FisheyeCamera {
+    origin: Vec3,
+    basis: (Vec3, Vec3, Vec3),
+    half_fov: f32,
+    aspect: f32,
+    projection: FisheyeProjection,
+}
+
+impl FisheyeCamera {
+    pub fn new(
+        lookfrom: Vec3,
+        lookat: Vec3,
+        vup: Vec3,
+        fov: f32,
+        aspect: f32,
+        projection: FisheyeProjection,
+    ) -> Self {
+        if fov <= 0.0 || fov > 360.0 {
+            panic!("fov must be in (0, 360]!");
+        }
+        Self {
+            origin: lookfrom,
+            basis: look_at_basis(lookfrom, lookat, vup),
+            half_fov: fov * PI / 360.0,
+            aspect,
+            projection,
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        let x = (2.0 * sample.u - 1.0) * self.aspect;
+        let y = 2.0 * sample.v - 1.0;
+        let r = (x * x + y * y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+        let theta = match self.projection {
+            FisheyeProjection::Equidistant => r * self.half_fov,
+            FisheyeProjection::Equisolid => 2.0 * (r * (self.half_fov / 2.0).sin()).asin(),
+        };
+        let (cos_phi, sin_phi) = if r > 0.0 { (x / r, y / r) } else { (1.0, 0.0) };
+        let local = Vec3::new(theta.sin() * cos_phi, theta.sin() * sin_phi, -theta.cos());
+        let direction = to_world(&self.basis, local);
+        Some(Ray::new(self.origin, direction))
+    }
+
+    fn center_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        self.generate_ray(sample)
+    }
+
+    /// `depth` is the distance from the camera.
+    fn project(&self, point: &Vec3) -> Option<(f32, f32, f32)> {
+        let (local, depth) = to_local(&self.basis, *point - self.origin)?;
+        let theta = (-local.z()).clamp(-1.0, 1.0).acos();
+        if theta > self.half_fov {
+            return None;
+        }
+        let r = match self.projection {
+            FisheyeProjection::Equidistant => theta / self.half_fov,
+            FisheyeProjection::Equisolid => (theta / 2.0).sin() / (self.half_fov / 2.0).sin(),
+        };
+        let phi = local.y().atan2(local.x());
+        let (x, y) = (r * phi.cos(), r * phi.sin());
+        on_film(0.5 * (x / self.aspect + 1.0), 0.5 * (y + 1.0), depth)
+    }
+}
+
+/// Faces of a cubemap in the usual +X, -X, +Y, -Y, +Z, -Z order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CubeFace {
+    PosX,
+    NegX,
+    PosY,
+    NegY,
+    PosZ,
+    NegZ,
+}
+
+impl CubeFace {
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::PosX,
+        CubeFace::NegX,
+        CubeFace::PosY,
+        CubeFace::NegY,
+        CubeFace::PosZ,
+        CubeFace::NegZ,
+    ];
+
+    /// World direction through face coordinates `s`, `t` in [-1, 1], `t`
+    /// pointing down the face image as in the OpenGL cubemap convention.
+    pub fn direction(&self, s: f32, t: f32) -> Vec3 {
+        let direction = match self {
+            CubeFace::PosX => Vec3::new(1.0, -t, -s),
+            CubeFace::NegX => Vec3::new(-1.0, -t, s),
+            CubeFace::PosY => Vec3::new(s, 1.0, t),
+            CubeFace::NegY => Vec3::new(s, -1.0, -t),
+            CubeFace::PosZ => Vec3::new(s, -t, 1.0),
+            CubeFace::NegZ => Vec3::new(-s, -t, -1.0),
+        };
+        direction.get_unit()
+    }
+
+    /// Face `direction` points at and the face coordinates `s`, `t` there.
+    pub fn from_direction(direction: &Vec3) -> (CubeFace, f32, f32) {
+        let (x, y, z) = (direction.x(), direction.y(), direction.z());
+        let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+        if ax >= ay && ax >= az {
+            if x > 0.0 {
+                (CubeFace::PosX, -z / ax, -y / ax)
+            } else {
+                (CubeFace::NegX, z / ax, -y / ax)
+            }
+        } else if ay >= az {
+            if y > 0.0 {
+                (CubeFace::PosY, x / ay, z / ay)
+            } else {
+                (CubeFace::NegY, x / ay, -z / ay)
+            }
+        } else if z > 0.0 {
+            (CubeFace::PosZ, x / az, -y / az)
+        } else {
+            (CubeFace::NegZ, -x / az, -y / az)
+        }
+    }
+}
+
+/// Arrangement of the six square faces in the output image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CubemapLayout {
+    /// 6:1 row of the faces in `CubeFace::ALL` order.
+    HorizontalStrip,
+    /// 1:6 column of the faces in `CubeFace::ALL` order.
+    VerticalStrip,
+    /// 4:3 cross with -X, +Z, +X, -Z in the middle row, +Y above and -Y
+    /// below +Z; the empty cells stay black.
+    HorizontalCross,
+}
+
+impl CubemapLayout {
+    /// Number of face columns and rows of the layout, i.e. the aspect ratio
+    /// the image must be rendered at.
+    pub fn grid(&self) -> (usize, usize) {
+        match self {
+            CubemapLayout::HorizontalStrip => (6, 1),
+            CubemapLayout::VerticalStrip => (1, 6),
+            CubemapLayout::HorizontalCross => (4, 3),
+        }
+    }
+
+    /// Face in the cell at `column`, `row` from the top left corner.
+    pub fn face(&self, column: usize, row: usize) -> Option<CubeFace> {
+        match self {
+            CubemapLayout::HorizontalStrip => CubeFace::ALL.get(column).copied(),
+            CubemapLayout::VerticalStrip => CubeFace::ALL.get(row).copied(),
+            CubemapLayout::HorizontalCross => match (column, row) {
+                (1, 0) => Some(CubeFace::PosY),
+                (0, 1) => Some(CubeFace::NegX),
+                (1, 1) => Some(CubeFace::PosZ),
+                (2, 1) => Some(CubeFace::PosX),
+                (3, 1) => Some(CubeFace::NegZ),
+                (1, 2) => Some(CubeFace::NegY),
+                _ => None,
+            },
+        }
+    }
+
+    /// Cell holding `face`, as column and row from the top left corner.
+    pub fn cell(&self, face: CubeFace) -> (usize, usize) {
+        let (n_columns, n_rows) = self.grid();
+        (0..n_rows)
+            .flat_map(|row| (0..n_columns).map(move |column| (column, row)))
+            .find(|&(column, row)| self.face(column, row) == Some(face))
+            .unwrap()
+    }
+}
+
+/// World-aligned cubemap camera at `position`, e.g. for reflection probes.
+#[derive(Clone)]
+pub struct CubemapCamera {
+    position: Vec3,
+    layout: CubemapLayout,
+}
+
+impl CubemapCamera {
+    pub fn new(position: Vec3, layout: CubemapLayout) -> Self {
+        Self { position, layout }
+    }
+
+    pub fn layout(&self) -> CubemapLayout {
+        self.layout
+    }
+
+    /// Image size fitting the layout with square faces of `face_size`
+    /// pixels.
+    pub fn image_size(&self, face_size: usize) -> (usize, usize) {
+        let (n_columns, n_rows) = self.layout.grid();
+        (n_columns * face_size, n_rows * face_size)
+    }
+}
+
+impl Camera for CubemapCamera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        let (n_columns, n_rows) = self.layout.grid();
+        let x = (sample.u * n_columns as f32).clamp(0.0, n_columns as f32 - 1e-4);
+        let y = ((1.0 - sample.v) * n_rows as f32).clamp(0.0, n_rows as f32 - 1e-4);
+        let face = self.layout.face(x as usize, y as usize)?;
+        let s = 2.0 * x.fract() - 1.0;
+        let t = 2.0 * y.fract() - 1.0;
+        Some(Ray::new(self.position, face.direction(s, t)))
+    }
+
+    fn center_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        self.generate_ray(sample)
+    }
+
+    /// `depth` is the distance from the camera.
+    fn project(&self, point: &Vec3) -> Option<(f32, f32, f32)> {
+        let d = *point - self.position;
+        let depth = d.length();
+        if depth == 0.0 {
+            return None;
+        }
+        let (face, s, t) = CubeFace::from_direction(&d);
+        let (column, row) = self.layout.cell(face);
+        let (n_columns, n_rows) = self.layout.grid();
+        let u = (column as f32 + 0.5 * (s + 1.0)) / n_columns as f32;
+        let v = 1.0 - (row as f32 + 0.5 * (t + 1.0)) / n_rows as f32;
+        on_film(u, v, depth)
+    }
+}
+
+/// Direction in the camera basis and length of the world vector `d`.
+fn to_local(basis: &(Vec3, Vec3, Vec3), d: Vec3) -> Option<(Vec3, f32)> {
+    let length = d.length();
+    if length == 0.0 {
+        return None;
+    }
+    let (u, v, w) = basis;
+    let local = Vec3::new(d.dot(u), d.dot(v), d.dot(w)).scale(1.0 / length);
+    Some((local, length))
+}
+
+fn to_world(basis: &(Vec3, Vec3, Vec3), local: Vec3) -> Vec3 {
+    let (u, v, w) = basis;
+    (u.scale(local.x()) + v.scale(local.y()) + w.scale(local.z())).get_unit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::assert_close;
+
+    fn looking_forward() -> (Vec3, Vec3, Vec3) {
+        (
+            Vec3::zeros(),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        )
+    }
+
+    #[test]
+    fn test_equirectangular_camera() {
+        let (lookfrom, lookat, vup) = looking_forward();
+        let camera = EquirectangularCamera::new(lookfrom, lookat, vup);
+        let direction = |u, v| {
+            camera
+                .generate_ray(&CameraSample::new(u, v))
+                .unwrap()
+                .direction
+        };
+        assert_close(direction(0.5, 0.5), Vec3::new(0.0, 0.0, -1.0), 1e-5);
+        assert_close(direction(0.75, 0.5), Vec3::new(1.0, 0.0, 0.0), 1e-5);
+        assert_close(direction(0.0, 0.5), Vec3::new(0.0, 0.0, 1.0), 1e-5);
+        assert_close(direction(0.3, 1.0), Vec3::new(0.0, 1.0, 0.0), 1e-5);
+    }
+
+    #[test]
+    fn test_fisheye_camera() {
+        let (lookfrom, lookat, vup) = looking_forward();
+        for projection in [FisheyeProjection::Equidistant, FisheyeProjection::Equisolid] {
+            let camera = FisheyeCamera::new(lookfrom, lookat, vup, 180.0, 2.0, projection);
+            let direction = |u, v| {
+                camera
+                    .generate_ray(&CameraSample::new(u, v))
+                    .map(|ray| ray.direction)
+            };
+            assert_close(
+                direction(0.5, 0.5).unwrap(),
+                Vec3::new(0.0, 0.0, -1.0),
+                1e-5,
+            );
+            // The rim of the image circle looks sideways.
+            assert_close(direction(0.5, 1.0).unwrap(), Vec3::new(0.0, 1.0, 0.0), 1e-5);
+            assert_close(
+                direction(0.75, 0.5).unwrap(),
+                Vec3::new(1.0, 0.0, 0.0),
+                1e-5,
+            );
+            assert!(direction(0.0, 0.5).is_none());
+        }
+    }
+
+    #[test]
+    fn test_cubemap_faces() {
+        for face in CubeFace::ALL {
+            let (s, t) = (0.3, -0.6);
+            let direction = face.direction(s, t);
+            // Inverse of the OpenGL face selection and coordinates.
+            let (x, y, z) = (direction.x(), direction.y(), direction.z());
+            let (major, sc, tc) = match face {
+                CubeFace::PosX => (x, -z, -y),
+                CubeFace::NegX => (-x, z, -y),
+                CubeFace::PosY => (y, x, z),
+                CubeFace::NegY => (-y, x, -z),
+                CubeFace::PosZ => (z, x, -y),
+                CubeFace::NegZ => (-z, -x, -y),
+            };
+            assert!(major > 0.0);
+            assert!((sc / major - s).abs() < 1e-5 && (tc / major - t).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_project() {
+        let (lookfrom, lookat, vup) = (
+            Vec3::new(1.0, 2.0, 3.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        let cameras: [Box<dyn Camera>; 4] = [
+            Box::new(EquirectangularCamera::new(lookfrom, lookat, vup)),
+            Box::new(FisheyeCamera::new(
+                lookfrom,
+                lookat,
+                vup,
+                200.0,
+                1.5,
+                FisheyeProjection::Equisolid,
+            )),
+            Box::new(CubemapCamera::new(lookfrom, CubemapLayout::HorizontalCross)),
+            Box::new(CubemapCamera::new(lookfrom, CubemapLayout::VerticalStrip)),
+        ];
+        for camera in cameras {
+            for (u, v) in [(0.3, 0.55), (0.6, 0.45), (0.45, 0.62)] {
+                let ray = camera.generate_ray(&CameraSample::new(u, v)).unwrap();
+                let (pu, pv, depth) = camera.project(&ray.get_point(4.0)).unwrap();
+                assert!((pu - u).abs() < 1e-4 && (pv - v).abs() < 1e-4);
+                assert!((depth - 4.0).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cubemap_cross_layout() {
+        let camera = CubemapCamera::new(Vec3::zeros(), CubemapLayout::HorizontalCross);
+        let direction = |u, v| {
+            camera
+                .generate_ray(&CameraSample::new(u, v))
+                .map(|ray| ray.direction)
+        };
+        // Centers of the cells, v counted from the bottom.
+        assert_close(
+            direction(0.375, 0.5).unwrap(),
+            Vec3::new(0.0, 0.0, 1.0),
+            1e-5,
+        );
+        assert_close(
+            direction(0.375, 5.0 / 6.0).unwrap(),
+            Vec3::new(0.0, 1.0, 0.0),
+            1e-5,
+        );
+        assert_close(
+            direction(0.125, 0.5).unwrap(),
+            Vec3::new(-1.0, 0.0, 0.0),
+            1e-5,
+        );
+        assert!(direction(0.125, 5.0 / 6.0).is_none());
+        // +Z and +X share their common edge.
+        assert_close(
+            direction(0.5 - 1e-6, 0.5).unwrap(),
+            direction(0.5 + 1e-6, 0.5).unwrap(),
+            1e-5,
+        );
+    }
+}
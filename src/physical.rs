@@ -0,0 +1,229 @@
+use std::f32::consts::PI;
+
+use crate::{
+    camera::{Camera, CameraSample, PerspectiveCamera},
+    ray::Ray,
+    rnd,
+    vec3::Vec3,
+};
+
+/// Exposure value at ISO 100 of a scene whose radiance is around one, i.e.
+/// the default sky, taken as a sunlit scene.
+const SCENE_EV100: f32 = 15.0;
+
+/// Photographic camera parameters. Lengths of the lens and sensor are in
+/// millimeters, scene units are meters.
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicalSettings {
+    pub focal_length: f32,
+    pub sensor_width: f32,
+    pub sensor_height: f32,
+    /// Focal length over the aperture diameter.
+    pub f_number: f32,
+    /// Exposure time in seconds.
+    pub shutter: f32,
+    pub iso: f32,
+    /// Distance of the plane in focus in meters.
+    pub focus_dist: f32,
+}
+
+impl Default for PhysicalSettings {
+    /// 50 mm lens at f/8 on a full frame sensor, 1/500 s at ISO 100.
+    fn default() -> Self {
+        Self {
+            focal_length: 50.0,
+            sensor_width: 36.0,
+            sensor_height: 24.0,
+            f_number: 8.0,
+            shutter: 1.0 / 500.0,
+            iso: 100.0,
+            focus_dist: 10.0,
+        }
+    }
+}
+
+impl PhysicalSettings {
+    /// Exposure value at ISO 100 of the aperture and shutter combination.
+    pub fn ev100(&self) -> f32 {
+        (self.f_number * self.f_number / self.shutter * 100.0 / self.iso).log2()
+    }
+
+    /// Exposure in stops for `ToneMapping::exposure`, zero for settings
+    /// metered for the default sky (e.g. f/8, 1/500 s, ISO 100).
+    pub fn exposure(&self) -> f32 {
+        SCENE_EV100 - self.ev100()
+    }
+
+    /// Vertical field of view in degrees of an image of `aspect` fitting the
+    /// sensor.
+    pub fn vfov(&self, aspect: f32) -> f32 {
+        let film_height = (self.sensor_width / aspect).min(self.sensor_height);
+        2.0 * (film_height / (2.0 * self.focal_length)).atan() * 180.0 / PI
+    }
+
+    /// Aperture diameter in meters.
+    pub fn aperture(&self) -> f32 {
+        self.focal_length / self.f_number * 0.001
+    }
+
+    /// Times in seconds the shutter opens and closes at.
+    pub fn shutter_interval(&self) -> (f32, f32) {
+        (0.0, self.shutter)
+    }
+}
+
+/// Thin lens camera set up from `PhysicalSettings`: the focal length and
+/// sensor give the field of view, the f-number the depth of field, and the
+/// shutter the times of the rays, drawn from `rnd` within the interval.
+/// The exposure isn't applied to the image; see `PhysicalSettings::exposure`.
+#[derive(Clone)]
+pub struct PhysicalCamera {
+    lens: PerspectiveCamera,
+    settings: PhysicalSettings,
+}
+
+impl PhysicalCamera {
+    pub fn new(
+        lookfrom: Vec3,
+        lookat: Vec3,
+        vup: Vec3,
+        aspect: f32,
+        settings: PhysicalSettings,
+    ) -> Self {
+        if settings.focal_length <= 0.0 || settings.f_number <= 0.0 {
+            panic!("focal_length and f_number must be positive!");
+        }
+        if settings.shutter <= 0.0 || settings.iso <= 0.0 {
+            panic!("shutter and iso must be positive!");
+        }
+        let lens = PerspectiveCamera::new(
+            lookfrom,
+            lookat,
+            vup,
+            settings.vfov(aspect),
+            aspect,
+            settings.aperture(),
+            settings.focus_dist,
+        );
+        Self { lens, settings }
+    }
+
+    pub fn settings(&self) -> &PhysicalSettings {
+        &self.settings
+    }
+
+    /// The underlying thin lens, e.g. to shape its aperture.
+    pub fn lens_mut(&mut self) -> &mut PerspectiveCamera {
+        &mut self.lens
+    }
+}
+
+impl Camera for PhysicalCamera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        let (open, close) = self.settings.shutter_interval();
+        let ray = self.lens.generate_ray(sample)?;
+        Some(ray.with_time(open + rnd::gen_f32() * (close - open)))
+    }
+
+    fn center_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        self.lens.center_ray(sample)
+    }
+
+    fn project(&self, point: &Vec3) -> Option<(f32, f32, f32)> {
+        self.lens.project(point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::surfaces::{Hit, Material, Sphere};
+
+    #[test]
+    fn test_physical_settings() {
+        let settings = PhysicalSettings::default();
+        assert!(settings.exposure().abs() < 0.1);
+        // One stop more light with a wider aperture, a slower shutter or a
+        // higher ISO.
+        let brighter = [
+            PhysicalSettings {
+                f_number: 8.0 / 2.0f32.sqrt(),
+                ..settings
+            },
+            PhysicalSettings {
+                shutter: 1.0 / 250.0,
+                ..settings
+            },
+            PhysicalSettings {
+                iso: 200.0,
+                ..settings
+            },
+        ];
+        for brighter in brighter {
+            assert!((brighter.exposure() - settings.exposure() - 1.0).abs() < 1e-4);
+        }
+
+        // 36 mm wide sensor with a 3:2 image: 24 mm high.
+        let vfov = settings.vfov(1.5);
+        assert!((vfov - 2.0 * (12.0f32 / 50.0).atan().to_degrees()).abs() < 1e-4);
+        assert!((settings.aperture() - 0.00625).abs() < 1e-7);
+    }
+
+    #[test]
+    fn test_shutter() {
+        let settings = PhysicalSettings {
+            shutter: 0.5,
+            ..Default::default()
+        };
+        let camera = PhysicalCamera::new(
+            Vec3::zeros(),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            1.5,
+            settings,
+        );
+        let (mut min_time, mut max_time) = (f32::MAX, 0.0f32);
+        for i in 0..100 {
+            rnd::seed(i);
+            let time = camera
+                .generate_ray(&CameraSample::new(0.5, 0.5))
+                .unwrap()
+                .time;
+            (min_time, max_time) = (min_time.min(time), max_time.max(time));
+        }
+        assert!((0.0..0.05).contains(&min_time));
+        assert!((0.45..0.5).contains(&max_time));
+    }
+
+    #[test]
+    fn test_motion_blur() {
+        let settings = PhysicalSettings {
+            shutter: 1.0,
+            focus_dist: 5.0,
+            ..Default::default()
+        };
+        let camera = PhysicalCamera::new(
+            Vec3::zeros(),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            1.5,
+            settings,
+        );
+        let material = Material::Lambertian {
+            attenuation: Vec3::ones(),
+        };
+        let sphere = Sphere::new(Vec3::new(-1.0, 0.0, -5.0), 0.5, material);
+        let moving = Sphere::new(Vec3::new(-1.0, 0.0, -5.0), 0.5, material)
+            .with_velocity(Vec3::new(2.0, 0.0, 0.0));
+        // The moving sphere covers the view center for half the shutter.
+        let (mut n_static, mut n_moving) = (0, 0);
+        for i in 0..1000 {
+            rnd::seed(i);
+            let ray = camera.generate_ray(&CameraSample::new(0.5, 0.5)).unwrap();
+            n_static += usize::from(sphere.hit(&ray, 0.001, f32::MAX).is_some());
+            n_moving += usize::from(moving.hit(&ray, 0.001, f32::MAX).is_some());
+        }
+        assert_eq!(n_static, 0);
+        assert!((400..600).contains(&n_moving), "{}", n_moving);
+    }
+}
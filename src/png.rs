@@ -0,0 +1,323 @@
+use std::io::{self, Read, Write};
+
+use crate::{
+    buffer::{invalid_data, ColorBuffer},
+    deflate::{zlib_compress, zlib_decompress},
+    vec3::Vec3,
+};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PngOptions {
+    pub bit_depth: BitDepth,
+    /// Writes an RGBA image instead of RGB.
+    pub alpha: bool,
+}
+
+impl Default for PngOptions {
+    fn default() -> Self {
+        Self {
+            bit_depth: BitDepth::Eight,
+            alpha: false,
+        }
+    }
+}
+
+/// Writes display-referred `colors` in [0, 1] (clamped) with optional
+/// `alphas` as a PNG image. Rows go top to bottom.
+pub fn write_png<W: Write>(
+    mut out: W,
+    width: usize,
+    height: usize,
+    colors: &[Vec3],
+    alphas: &[f32],
+    options: &PngOptions,
+) -> io::Result<()> {
+    let n_channels = if options.alpha { 4 } else { 3 };
+    let n_bytes = match options.bit_depth {
+        BitDepth::Eight => 1,
+        BitDepth::Sixteen => 2,
+    };
+    let bpp = n_channels * n_bytes;
+    let row_len = width * bpp;
+
+    let mut raw = Vec::with_capacity(height * row_len);
+    for (idx, color) in colors.iter().enumerate() {
+        let alpha = alphas.get(idx).copied().unwrap_or(1.0);
+        let channels = [color.x(), color.y(), color.z(), alpha];
+        for &value in &channels[..n_channels] {
+            let value = value.clamp(0.0, 1.0);
+            match options.bit_depth {
+                BitDepth::Eight => raw.push((value * 255.0).round() as u8),
+                BitDepth::Sixteen => {
+                    raw.extend_from_slice(&((value * 65535.0).round() as u16).to_be_bytes())
+                }
+            }
+        }
+    }
+
+    let mut filtered = Vec::with_capacity(height * (row_len + 1));
+    let zeros = vec![0; row_len];
+    for y in 0..height {
+        let row = &raw[y * row_len..(y + 1) * row_len];
+        let prev_row = if y > 0 {
+            &raw[(y - 1) * row_len..y * row_len]
+        } else {
+            &zeros
+        };
+        filter_row(row, prev_row, bpp, &mut filtered);
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.push((n_bytes * 8) as u8);
+    ihdr.push(if options.alpha { 6 } else { 2 });
+    // Deflate compression, adaptive filtering, no interlace.
+    ihdr.extend_from_slice(&[0, 0, 0]);
+
+    out.write_all(&SIGNATURE)?;
+    write_chunk(&mut out, b"IHDR", &ihdr)?;
+    write_chunk(&mut out, b"IDAT", &zlib_compress(&filtered))?;
+    write_chunk(&mut out, b"IEND", &[])?;
+    out.flush()
+}
+
+/// Reads a non-interlaced grayscale, RGB or 8 bit palette PNG with 8 or 16
+/// bits per channel as display colors in [0, 1], dropping any alpha.
+pub fn read_png<R: Read>(mut inp: R) -> io::Result<ColorBuffer> {
+    let mut signature = [0; 8];
+    inp.read_exact(&mut signature)?;
+    if signature != SIGNATURE {
+        return Err(invalid_data("not a PNG file"));
+    }
+    let (mut ihdr, mut palette, mut idat) = (vec![], vec![], vec![]);
+    loop {
+        let (kind, data) = read_chunk(&mut inp)?;
+        match &kind {
+            b"IHDR" => ihdr = data,
+            b"PLTE" => palette = data,
+            b"IDAT" => idat.extend_from_slice(&data),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+    if ihdr.len() != 13 {
+        return Err(invalid_data("missing PNG header"));
+    }
+    let width = u32::from_be_bytes([ihdr[0], ihdr[1], ihdr[2], ihdr[3]]) as usize;
+    let height = u32::from_be_bytes([ihdr[4], ihdr[5], ihdr[6], ihdr[7]]) as usize;
+    let (bit_depth, color_type, interlace) = (ihdr[8], ihdr[9], ihdr[12]);
+    let n_channels = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(invalid_data("bad PNG color type")),
+    };
+    let supported = match color_type {
+        3 => bit_depth == 8,
+        _ => bit_depth == 8 || bit_depth == 16,
+    };
+    if !supported || interlace != 0 {
+        return Err(invalid_data("unsupported PNG format"));
+    }
+    let n_bytes = bit_depth as usize / 8;
+    let bpp = n_channels * n_bytes;
+    let row_len = width * bpp;
+
+    let filtered = zlib_decompress(&idat)?;
+    if filtered.len() < height * (row_len + 1) {
+        return Err(invalid_data("truncated PNG image data"));
+    }
+    let mut raw = vec![0; height * row_len];
+    let zeros = vec![0; row_len];
+    for y in 0..height {
+        let line = &filtered[y * (row_len + 1)..(y + 1) * (row_len + 1)];
+        let filter = line[0];
+        if filter > 4 {
+            return Err(invalid_data("bad PNG filter type"));
+        }
+        let (done, rest) = raw.split_at_mut(y * row_len);
+        let prev_row = if y > 0 {
+            &done[(y - 1) * row_len..]
+        } else {
+            &zeros
+        };
+        let row = &mut rest[..row_len];
+        for i in 0..row_len {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let c = if i >= bpp { prev_row[i - bpp] } else { 0 };
+            row[i] = line[i + 1].wrapping_add(predict(filter, a, prev_row[i], c));
+        }
+    }
+
+    let value = |pixel: &[u8], channel: usize| match n_bytes {
+        1 => pixel[channel] as f32 / 255.0,
+        _ => u16::from_be_bytes([pixel[2 * channel], pixel[2 * channel + 1]]) as f32 / 65535.0,
+    };
+    let colors = raw
+        .chunks(bpp)
+        .map(|pixel| match color_type {
+            0 | 4 => Ok(Vec3::ones().scale(value(pixel, 0))),
+            2 | 6 => Ok(Vec3::new(value(pixel, 0), value(pixel, 1), value(pixel, 2))),
+            _ => {
+                let idx = 3 * pixel[0] as usize;
+                let rgb = palette
+                    .get(idx..idx + 3)
+                    .ok_or_else(|| invalid_data("PNG palette index out of range"))?;
+                Ok(Vec3::new(rgb[0] as f32, rgb[1] as f32, rgb[2] as f32).scale(1.0 / 255.0))
+            }
+        })
+        .collect::<io::Result<_>>()?;
+    Ok(ColorBuffer::new(width, height, colors))
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let mut crc_data = kind.to_vec();
+    crc_data.extend_from_slice(data);
+    out.write_all(&crc32(&crc_data).to_be_bytes())
+}
+
+/// Reads a chunk, checking its CRC.
+fn read_chunk<R: Read>(inp: &mut R) -> io::Result<([u8; 4], Vec<u8>)> {
+    let mut header = [0; 8];
+    inp.read_exact(&mut header)?;
+    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let mut data = vec![0; length + 4];
+    inp.read_exact(&mut data)?;
+    let crc = data.split_off(length);
+    let mut crc_data = header[4..].to_vec();
+    crc_data.extend_from_slice(&data);
+    if crc32(&crc_data).to_be_bytes() != crc[..] {
+        return Err(invalid_data("PNG chunk CRC mismatch"));
+    }
+    Ok(([header[4], header[5], header[6], header[7]], data))
+}
+
+/// Appends `row` with the filter type byte, picking the filter with the
+/// smallest sum of absolute differences.
+fn filter_row(row: &[u8], prev_row: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    let mut best: Option<(u64, u8, Vec<u8>)> = None;
+    for filter in 0..5u8 {
+        let filtered: Vec<u8> = (0..row.len())
+            .map(|i| {
+                let a = if i >= bpp { row[i - bpp] } else { 0 };
+                let b = prev_row[i];
+                let c = if i >= bpp { prev_row[i - bpp] } else { 0 };
+                row[i].wrapping_sub(predict(filter, a, b, c))
+            })
+            .collect();
+        let cost = filtered
+            .iter()
+            .map(|&v| (v as i8).unsigned_abs() as u64)
+            .sum();
+        if best
+            .as_ref()
+            .is_none_or(|(best_cost, _, _)| cost < *best_cost)
+        {
+            best = Some((cost, filter, filtered));
+        }
+    }
+    let (_, filter, filtered) = best.unwrap();
+    out.push(filter);
+    out.extend_from_slice(&filtered);
+}
+
+/// Value `filter` predicts from the bytes to the left (`a`), above (`b`)
+/// and above left (`c`).
+fn predict(filter: u8, a: u8, b: u8, c: u8) -> u8 {
+    match filter {
+        0 => 0,
+        1 => a,
+        2 => b,
+        3 => ((a as u16 + b as u16) / 2) as u8,
+        _ => paeth(a, b, c),
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+    }
+
+    #[test]
+    fn test_read_png() {
+        let (width, height) = (7, 5);
+        let colors: Vec<Vec3> = (0..width * height)
+            .map(|i| Vec3::new(i as f32 / 34.0, (i % 3) as f32 / 2.0, 0.25))
+            .collect();
+        let alphas = vec![0.5; width * height];
+        for bit_depth in [BitDepth::Eight, BitDepth::Sixteen] {
+            for alpha in [false, true] {
+                let mut bytes = vec![];
+                let options = PngOptions { bit_depth, alpha };
+                write_png(&mut bytes, width, height, &colors, &alphas, &options).unwrap();
+                let image = read_png(&bytes[..]).unwrap();
+                assert_eq!((image.width, image.height), (width, height));
+                for (a, b) in image.colors.iter().zip(colors.iter()) {
+                    assert!((*a - *b).length() < 0.5 / 255.0 * 3.0f32.sqrt());
+                }
+            }
+        }
+
+        let mut bytes = vec![];
+        write_png(
+            &mut bytes,
+            width,
+            height,
+            &colors,
+            &[],
+            &PngOptions::default(),
+        )
+        .unwrap();
+        bytes[40] ^= 1;
+        assert!(read_png(&bytes[..]).is_err());
+    }
+}
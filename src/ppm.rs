@@ -0,0 +1,222 @@
+use std::io::{self, BufRead, Write};
+
+use crate::{
+    buffer::{invalid_data, ColorBuffer},
+    vec3::Vec3,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PpmFormat {
+    /// Plain text `P3`.
+    Ascii,
+    /// Raw bytes `P6`.
+    Binary,
+}
+
+/// Writes display-referred `colors` in [0, 1] (clamped) as an 8-bit PPM.
+pub fn write_ppm<W: Write>(
+    mut out: W,
+    width: usize,
+    height: usize,
+    colors: &[Vec3],
+    format: PpmFormat,
+) -> io::Result<()> {
+    match format {
+        PpmFormat::Ascii => {
+            write!(out, "P3\n{} {}\n255\n", width, height)?;
+            for color in colors {
+                let [r, g, b] = quantize(color);
+                writeln!(out, "{} {} {}", r, g, b)?;
+            }
+        }
+        PpmFormat::Binary => {
+            write!(out, "P6\n{} {}\n255\n", width, height)?;
+            for color in colors {
+                out.write_all(&quantize(color))?;
+            }
+        }
+    }
+    out.flush()
+}
+
+/// Writes linear `colors` as a little-endian PFM. Rows are given top to
+/// bottom and stored bottom to top, as the format requires.
+pub fn write_pfm<W: Write>(
+    mut out: W,
+    width: usize,
+    height: usize,
+    colors: &[Vec3],
+) -> io::Result<()> {
+    write!(out, "PF\n{} {}\n-1.0\n", width, height)?;
+    for row in colors.chunks(width).rev() {
+        for color in row {
+            for i in 0..3 {
+                out.write_all(&color[i].to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+/// Reads a `P3` or `P6` PPM, scaling the values to [0, 1].
+pub fn read_ppm<R: BufRead>(mut inp: R) -> io::Result<ColorBuffer> {
+    let magic = read_token(&mut inp)?;
+    let width = parse_token::<usize, _>(&mut inp)?;
+    let height = parse_token::<usize, _>(&mut inp)?;
+    let max_value = parse_token::<u32, _>(&mut inp)?;
+    if max_value == 0 || max_value > 65535 {
+        return Err(invalid_data("bad PPM max value"));
+    }
+    let scale = 1.0 / max_value as f32;
+
+    let n_values = width * height * 3;
+    let values: Vec<f32> = match magic.as_str() {
+        "P3" => (0..n_values)
+            .map(|_| parse_token::<u32, _>(&mut inp).map(|v| v as f32 * scale))
+            .collect::<io::Result<_>>()?,
+        "P6" => {
+            let n_bytes = if max_value > 255 { 2 } else { 1 };
+            let mut bytes = vec![0; n_values * n_bytes];
+            inp.read_exact(&mut bytes)?;
+            bytes
+                .chunks(n_bytes)
+                .map(|v| match v {
+                    [v] => *v as f32 * scale,
+                    _ => u16::from_be_bytes([v[0], v[1]]) as f32 * scale,
+                })
+                .collect()
+        }
+        _ => return Err(invalid_data("not a PPM file")),
+    };
+    Ok(to_buffer(width, height, &values, 3))
+}
+
+/// Reads a color (`PF`) or grayscale (`Pf`) PFM.
+pub fn read_pfm<R: BufRead>(mut inp: R) -> io::Result<ColorBuffer> {
+    let magic = read_token(&mut inp)?;
+    let n_channels = match magic.as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid_data("not a PFM file")),
+    };
+    let width = parse_token::<usize, _>(&mut inp)?;
+    let height = parse_token::<usize, _>(&mut inp)?;
+    let scale = parse_token::<f32, _>(&mut inp)?;
+
+    let mut bytes = vec![0; width * height * n_channels * 4];
+    inp.read_exact(&mut bytes)?;
+    let values: Vec<f32> = bytes
+        .chunks(4)
+        .map(|v| {
+            let v = [v[0], v[1], v[2], v[3]];
+            if scale < 0.0 {
+                f32::from_le_bytes(v)
+            } else {
+                f32::from_be_bytes(v)
+            }
+        })
+        .collect();
+
+    let mut buffer = to_buffer(width, height, &values, n_channels);
+    buffer.colors = buffer
+        .colors
+        .chunks(width.max(1))
+        .rev()
+        .flatten()
+        .copied()
+        .collect();
+    Ok(buffer)
+}
+
+pub(crate) fn quantize(color: &Vec3) -> [u8; 3] {
+    [0, 1, 2].map(|i| (color[i].clamp(0.0, 1.0) * 255.99) as u8)
+}
+
+fn to_buffer(width: usize, height: usize, values: &[f32], n_channels: usize) -> ColorBuffer {
+    let colors = values
+        .chunks(n_channels)
+        .map(|v| match v {
+            [r, g, b] => Vec3::new(*r, *g, *b),
+            _ => Vec3::new(v[0], v[0], v[0]),
+        })
+        .collect();
+    ColorBuffer::new(width, height, colors)
+}
+
+/// Reads the next whitespace separated header token, skipping `#` comments,
+/// and consumes the single whitespace character following it.
+fn read_token<R: BufRead>(inp: &mut R) -> io::Result<String> {
+    let mut token = String::new();
+    let mut byte = [0];
+    loop {
+        inp.read_exact(&mut byte)?;
+        match byte[0] {
+            b'#' if token.is_empty() => {
+                let mut comment = vec![];
+                inp.read_until(b'\n', &mut comment)?;
+            }
+            c if c.is_ascii_whitespace() => {
+                if !token.is_empty() {
+                    return Ok(token);
+                }
+            }
+            c => token.push(c as char),
+        }
+    }
+}
+
+fn parse_token<T: std::str::FromStr, R: BufRead>(inp: &mut R) -> io::Result<T> {
+    let token = read_token(inp)?;
+    token
+        .parse()
+        .map_err(|_| invalid_data(&format!("bad header value: {}", token)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: usize, height: usize) -> Vec<Vec3> {
+        (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                Vec3::new(x as f32 / width as f32, 0.25, y as f32 / height as f32)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ppm_roundtrip() {
+        let colors = gradient(5, 3);
+        for format in [PpmFormat::Ascii, PpmFormat::Binary] {
+            let mut bytes = vec![];
+            write_ppm(&mut bytes, 5, 3, &colors, format).unwrap();
+            let buffer = read_ppm(&bytes[..]).unwrap();
+            assert_eq!((buffer.width, buffer.height), (5, 3));
+            for (a, b) in buffer.colors.iter().zip(colors.iter()) {
+                let diff = (*a - *b).get_abs();
+                assert!(diff.x().max(diff.y()).max(diff.z()) < 1.0 / 255.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_ppm_comments() {
+        let bytes = b"P3\n# comment\n2 1\n# another\n255\n255 0 0\n0 0 255\n";
+        let buffer = read_ppm(&bytes[..]).unwrap();
+        assert_eq!(
+            buffer.colors,
+            vec![Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)]
+        );
+    }
+
+    #[test]
+    fn test_pfm_roundtrip() {
+        let colors = gradient(4, 3);
+        let mut bytes = vec![];
+        write_pfm(&mut bytes, 4, 3, &colors).unwrap();
+        let buffer = read_pfm(&bytes[..]).unwrap();
+        assert_eq!((buffer.width, buffer.height), (4, 3));
+        assert_eq!(buffer.colors, colors);
+    }
+}
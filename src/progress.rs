@@ -0,0 +1,123 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Snapshot of a running render, passed to the progress observer.
+pub struct Progress {
+    pub tiles_done: usize,
+    pub n_tiles: usize,
+    /// Camera samples rendered so far over all pixels.
+    pub n_samples: usize,
+    pub elapsed: Duration,
+    /// Wall-clock budget of a time-budgeted render.
+    pub budget: Option<Duration>,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f32 {
+        if let Some(budget) = self.budget {
+            return (self.elapsed.as_secs_f32() / budget.as_secs_f32()).min(1.0);
+        }
+        if self.n_tiles == 0 {
+            return 1.0;
+        }
+        self.tiles_done as f32 / self.n_tiles as f32
+    }
+
+    pub fn samples_per_sec(&self) -> f32 {
+        let secs = self.elapsed.as_secs_f32();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.n_samples as f32 / secs
+    }
+
+    /// Estimated time left, `None` until the first tile is finished.
+    pub fn eta(&self) -> Option<Duration> {
+        if let Some(budget) = self.budget {
+            return Some(budget.saturating_sub(self.elapsed));
+        }
+        if self.tiles_done == 0 {
+            return None;
+        }
+        let tiles_left = self.n_tiles.saturating_sub(self.tiles_done);
+        Some(
+            self.elapsed
+                .mul_f64(tiles_left as f64 / self.tiles_done as f64),
+        )
+    }
+}
+
+/// Observer called from the worker threads after every finished tile.
+pub type ProgressObserver = Box<dyn Fn(&Progress) + Sync>;
+
+/// Shared flag to stop a render from another thread. Workers finish their
+/// current tile and return, leaving the image with the samples rendered so far.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+pub(crate) struct ProgressTracker {
+    start: Instant,
+    budget: Option<Duration>,
+    n_tiles: usize,
+    tiles_done: AtomicUsize,
+    n_samples: AtomicUsize,
+}
+
+impl ProgressTracker {
+    pub fn new(n_tiles: usize) -> Self {
+        Self {
+            start: Instant::now(),
+            budget: None,
+            n_tiles,
+            tiles_done: AtomicUsize::new(0),
+            n_samples: AtomicUsize::new(0),
+        }
+    }
+
+    /// Tracker of a render that stops once `budget` has elapsed.
+    pub fn with_budget(budget: Duration) -> Self {
+        Self {
+            budget: Some(budget),
+            ..Self::new(0)
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.budget
+            .is_some_and(|budget| self.start.elapsed() >= budget)
+    }
+
+    /// Records a finished tile with `n_samples` camera samples.
+    pub fn add_tile(&self, n_samples: usize) -> Progress {
+        let tiles_done = self.tiles_done.fetch_add(1, Ordering::Relaxed) + 1;
+        let n_samples = self.n_samples.fetch_add(n_samples, Ordering::Relaxed) + n_samples;
+        Progress {
+            tiles_done,
+            n_tiles: self.n_tiles,
+            n_samples,
+            elapsed: self.start.elapsed(),
+            budget: self.budget,
+        }
+    }
+}
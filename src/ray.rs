@@ -3,6 +3,8 @@ use crate::vec3::Vec3;
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    /// Instant in seconds the ray travels at, for motion blur.
+    pub time: f32,
 }
 
 impl Ray {
@@ -10,7 +12,16 @@ impl Ray {
         if !direction.is_unit() {
             panic!("direction must be unit!");
         }
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            time: 0.0,
+        }
+    }
+
+    pub fn with_time(mut self, time: f32) -> Self {
+        self.time = time;
+        self
     }
 
     pub fn get_point(&self, distance: f32) -> Vec3 {
@@ -0,0 +1,26 @@
+use std::cell::RefCell;
+
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+thread_local! {
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_entropy());
+}
+
+/// Reseeds the random generator of the current thread, making everything
+/// sampled on it until the next reseed reproducible.
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(seed));
+}
+
+/// Uniform random number in [0, 1) from the generator of the current thread.
+pub fn gen_f32() -> f32 {
+    RNG.with(|rng| rng.borrow_mut().gen::<f32>())
+}
+
+/// Derives an independent seed for the `idx`-th item of a stream (SplitMix64).
+pub fn mix_seed(seed: u64, idx: u64) -> u64 {
+    let mut z = seed.wrapping_add(idx.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
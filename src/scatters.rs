@@ -1,12 +1,10 @@
-use crate::{ray::Ray, surfaces::HitRes, vec3::Vec3};
-use rand::Rng;
+use crate::{ray::Ray, rnd, surfaces::HitRes, vec3::Vec3};
 
 fn rnd_vec_in_sphere() -> Vec3 {
-    let mut rng = rand::thread_rng();
     let mut p: Vec3;
     let ones = Vec3::ones();
     loop {
-        p = Vec3::new(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()).scale(2.0);
+        p = Vec3::new(rnd::gen_f32(), rnd::gen_f32(), rnd::gen_f32()).scale(2.0);
         p -= ones;
         if p.length() < 1.0 {
             break p;
@@ -50,15 +48,14 @@ pub fn scatter_dielectric(inp_ray: &Ray, hit_res: &HitRes, ref_k: f32) -> Option
 
     if let Some(refracted) = refract(&inp_ray.direction, &outward_norm, ni_over_nt) {
         reflect_prob = schlick(cosine, ref_k);
-        let mut rng = rand::thread_rng();
-        if reflect_prob > rng.gen::<f32>() {
-            return Some(Ray::new(hit_res.point, reflected.get_unit()));
+        if reflect_prob > rnd::gen_f32() {
+            Some(Ray::new(hit_res.point, reflected.get_unit()))
         } else {
-            return Some(Ray::new(hit_res.point, refracted.get_unit()));
+            Some(Ray::new(hit_res.point, refracted.get_unit()))
         }
     } else {
-        return Some(Ray::new(hit_res.point, reflected.get_unit()));
-    };
+        Some(Ray::new(hit_res.point, reflected.get_unit()))
+    }
 }
 
 fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
@@ -71,10 +68,10 @@ fn refract(v: &Vec3, n: &Vec3, ni_over_nt: f32) -> Option<Vec3> {
     let d = 1.0 - ni_over_nt * ni_over_nt * (1.0 - dt * dt);
     if d > 0.0 {
         let refracted = (uv - n.scale(dt)).scale(ni_over_nt) - n.scale(d.sqrt());
-        return Some(refracted);
+        Some(refracted)
     } else {
-        return None;
-    };
+        None
+    }
 }
 
 fn schlick(cosine: f32, ref_k: f32) -> f32 {
@@ -0,0 +1,262 @@
+use std::f32::consts::PI;
+
+use crate::{
+    camera::{look_at_basis, rnd_vec_in_disc, Camera, CameraSample},
+    ray::Ray,
+    vec3::Vec3,
+};
+
+/// Arrangement of the two eye views in the output image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StereoLayout {
+    /// Left eye in the left half, right eye in the right half.
+    SideBySide,
+    /// Left eye in the top half, right eye in the bottom half.
+    OverUnder,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+impl Eye {
+    /// Direction of the eye offset along the camera right axis.
+    fn sign(&self) -> f32 {
+        match self {
+            Eye::Left => -1.0,
+            Eye::Right => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StereoRig {
+    /// Distance between the eyes in scene units.
+    pub interocular: f32,
+    /// Distance of the zero parallax plane, where the eye views line up.
+    /// May be infinite for parallel eyes with omni-directional stereo.
+    pub convergence: f32,
+    pub layout: StereoLayout,
+}
+
+impl Default for StereoRig {
+    fn default() -> Self {
+        Self {
+            interocular: 0.065,
+            convergence: 10.0,
+            layout: StereoLayout::SideBySide,
+        }
+    }
+}
+
+impl StereoRig {
+    /// Size of the image holding both eye views of `eye_width` x
+    /// `eye_height` pixels.
+    pub fn image_size(&self, eye_width: usize, eye_height: usize) -> (usize, usize) {
+        match self.layout {
+            StereoLayout::SideBySide => (2 * eye_width, eye_height),
+            StereoLayout::OverUnder => (eye_width, 2 * eye_height),
+        }
+    }
+
+    /// Eye rendered at film position `sample` and the position within the
+    /// eye view.
+    fn split(&self, sample: &CameraSample) -> (Eye, CameraSample) {
+        let (u, v) = (sample.u, sample.v);
+        let (eye, u, v) = match self.layout {
+            StereoLayout::SideBySide if u < 0.5 => (Eye::Left, 2.0 * u, v),
+            StereoLayout::SideBySide => (Eye::Right, 2.0 * u - 1.0, v),
+            StereoLayout::OverUnder if v >= 0.5 => (Eye::Left, u, 2.0 * v - 1.0),
+            StereoLayout::OverUnder => (Eye::Right, u, 2.0 * v),
+        };
+        (eye, CameraSample::new(u, v))
+    }
+}
+
+enum Projection {
+    /// Off-axis perspective eyes sharing the frustum at the convergence
+    /// plane, which is also the focus plane.
+    Perspective {
+        half_width: f32,
+        half_height: f32,
+        lens_radius: f32,
+    },
+    /// Omni-directional stereo equirectangular panorama, the eyes circling
+    /// the center as the view turns around.
+    Omnidirectional,
+}
+
+/// Camera rendering both eyes of a stereo pair into one image laid out by
+/// `StereoRig::layout`.
+pub struct StereoCamera {
+    origin: Vec3,
+    basis: (Vec3, Vec3, Vec3),
+    rig: StereoRig,
+    projection: Projection,
+}
+
+impl StereoCamera {
+    /// Perspective stereo pair looking from `lookfrom` towards `lookat`;
+    /// `vfov` and `aspect` are those of a single eye view, and
+    /// `rig.convergence` must be finite.
+    pub fn perspective(
+        lookfrom: Vec3,
+        lookat: Vec3,
+        vup: Vec3,
+        vfov: f32,
+        aspect: f32,
+        aperture: f32,
+        rig: StereoRig,
+    ) -> Self {
+        if !(rig.convergence > 0.0 && rig.convergence.is_finite()) {
+            panic!("convergence must be positive and finite!");
+        }
+        let half_height = (vfov * PI / 360.0).tan();
+        Self {
+            origin: lookfrom,
+            basis: look_at_basis(lookfrom, lookat, vup),
+            rig,
+            projection: Projection::Perspective {
+                half_width: aspect * half_height,
+                half_height,
+                lens_radius: aperture / 2.0,
+            },
+        }
+    }
+
+    /// Omni-directional stereo (ODS) equirectangular pair, each eye view
+    /// 2:1 and centered on `lookat`. The eye separation fades out towards
+    /// the poles to avoid the swapped eyes of looking straight up or down.
+    /// An infinite `rig.convergence` makes the eyes look parallel.
+    pub fn omnidirectional(lookfrom: Vec3, lookat: Vec3, vup: Vec3, rig: StereoRig) -> Self {
+        if rig.convergence.is_nan() || rig.convergence <= 0.0 {
+            panic!("convergence must be positive!");
+        }
+        Self {
+            origin: lookfrom,
+            basis: look_at_basis(lookfrom, lookat, vup),
+            rig,
+            projection: Projection::Omnidirectional,
+        }
+    }
+
+    pub fn rig(&self) -> &StereoRig {
+        &self.rig
+    }
+
+    /// Ray of a single `eye` through `sample` of its own view.
+    pub fn eye_ray(&self, eye: Eye, sample: &CameraSample) -> Ray {
+        self.lens_ray(eye, sample, true)
+    }
+
+    /// Ray of `eye` through a random point of the lens, or its center.
+    fn lens_ray(&self, eye: Eye, sample: &CameraSample, sample_lens: bool) -> Ray {
+        let (u, v, w) = self.basis;
+        let half_iod = 0.5 * self.rig.interocular * eye.sign();
+        let convergence = self.rig.convergence;
+        match self.projection {
+            Projection::Perspective {
+                half_width,
+                half_height,
+                lens_radius,
+            } => {
+                let x = (2.0 * sample.u - 1.0) * half_width;
+                let y = (2.0 * sample.v - 1.0) * half_height;
+                let target = self.origin + (u.scale(x) + v.scale(y) - w).scale(convergence);
+                let rd = if sample_lens {
+                    rnd_vec_in_disc().scale(lens_radius)
+                } else {
+                    Vec3::zeros()
+                };
+                let origin = self.origin + u.scale(half_iod + rd.x()) + v.scale(rd.y());
+                Ray::new(origin, (target - origin).get_unit())
+            }
+            Projection::Omnidirectional => {
+                let phi = (sample.u - 0.5) * 2.0 * PI;
+                let theta = (sample.v - 0.5) * PI;
+                let direction = w.scale(-theta.cos() * phi.cos())
+                    + u.scale(theta.cos() * phi.sin())
+                    + v.scale(theta.sin());
+                let right = u.scale(phi.cos()) + w.scale(phi.sin());
+                let origin = self.origin + right.scale(half_iod * theta.cos());
+                if convergence.is_finite() {
+                    let target = self.origin + direction.scale(convergence);
+                    Ray::new(origin, (target - origin).get_unit())
+                } else {
+                    Ray::new(origin, direction.get_unit())
+                }
+            }
+        }
+    }
+}
+
+impl Camera for StereoCamera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        let (eye, sample) = self.rig.split(sample);
+        Some(self.eye_ray(eye, &sample))
+    }
+
+    fn center_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        let (eye, sample) = self.rig.split(sample);
+        Some(self.lens_ray(eye, &sample, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::assert_close;
+
+    #[test]
+    fn test_perspective_convergence() {
+        let rig = StereoRig {
+            interocular: 0.2,
+            convergence: 4.0,
+            layout: StereoLayout::SideBySide,
+        };
+        let camera = StereoCamera::perspective(
+            Vec3::zeros(),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+            1.0,
+            0.0,
+            rig,
+        );
+        assert_eq!(rig.image_size(100, 50), (200, 50));
+        let left = camera.generate_ray(&CameraSample::new(0.3, 0.7)).unwrap();
+        let right = camera.generate_ray(&CameraSample::new(0.8, 0.7)).unwrap();
+        assert_close(left.origin, Vec3::new(-0.1, 0.0, 0.0), 1e-5);
+        assert_close(right.origin, Vec3::new(0.1, 0.0, 0.0), 1e-5);
+        // Both eyes meet on the convergence plane.
+        let t_left = -4.0 / left.direction.z();
+        let t_right = -4.0 / right.direction.z();
+        assert_close(left.get_point(t_left), right.get_point(t_right), 1e-5);
+    }
+
+    #[test]
+    fn test_omnidirectional() {
+        let rig = StereoRig {
+            interocular: 0.2,
+            convergence: f32::INFINITY,
+            layout: StereoLayout::OverUnder,
+        };
+        let camera = StereoCamera::omnidirectional(
+            Vec3::zeros(),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            rig,
+        );
+        // Left eye on top; turned to +X the eyes lie along the Z axis.
+        let left = camera.generate_ray(&CameraSample::new(0.75, 0.75)).unwrap();
+        assert_close(left.direction, Vec3::new(1.0, 0.0, 0.0), 1e-5);
+        assert_close(left.origin, Vec3::new(0.0, 0.0, -0.1), 1e-5);
+        let right = camera.generate_ray(&CameraSample::new(0.75, 0.25)).unwrap();
+        assert_close(right.origin, Vec3::new(0.0, 0.0, 0.1), 1e-5);
+        // No eye separation looking straight up.
+        let up = camera.generate_ray(&CameraSample::new(0.1, 1.0)).unwrap();
+        assert_close(up.origin, Vec3::zeros(), 1e-5);
+    }
+}
@@ -1,6 +1,6 @@
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
-use crate::{ray::Ray, vec3::Vec3};
+use crate::{environment::Environment, ray::Ray, vec3::Vec3};
 
 pub trait Hit {
     fn hit(&self, ray: &Ray, t_max: f32, t_min: f32) -> Option<HitRes>;
@@ -13,11 +13,33 @@ pub enum Material {
     Dielectric { ref_k: f32 },
 }
 
+impl Material {
+    /// Index of the material kind, recorded by the material ID AOV.
+    pub fn id(&self) -> usize {
+        match self {
+            Material::Lambertian { .. } => 0,
+            Material::Metal { .. } => 1,
+            Material::Dielectric { .. } => 2,
+        }
+    }
+
+    /// Surface color, recorded by the albedo AOV.
+    pub fn albedo(&self) -> Vec3 {
+        match self {
+            Material::Lambertian { attenuation } => *attenuation,
+            Material::Metal { attenuation, .. } => *attenuation,
+            Material::Dielectric { .. } => Vec3::ones(),
+        }
+    }
+}
+
 pub struct HitRes {
     pub point: Vec3,
     pub distance: f32,
     pub norm: Vec3,
     pub material: Material,
+    /// Index of the hit surface in `Surfaces`.
+    pub object_id: usize,
 }
 
 impl HitRes {
@@ -30,6 +52,7 @@ impl HitRes {
             distance,
             norm,
             material,
+            object_id: 0,
         }
     }
 }
@@ -38,6 +61,8 @@ pub struct Sphere {
     center: Vec3,
     radius: f32,
     material: Material,
+    /// Scene units per second, see `with_velocity`.
+    velocity: Vec3,
 }
 
 impl Sphere {
@@ -46,13 +71,25 @@ impl Sphere {
             center,
             radius,
             material,
+            velocity: Vec3::zeros(),
         }
     }
+
+    /// Sphere moving from `center` at time zero with `velocity` in scene
+    /// units per second, blurred over the shutter of a `PhysicalCamera`.
+    pub fn with_velocity(self, velocity: Vec3) -> Self {
+        Self { velocity, ..self }
+    }
+
+    fn center_at(&self, time: f32) -> Vec3 {
+        self.center + self.velocity.scale(time)
+    }
 }
 
 impl Hit for Sphere {
     fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRes> {
-        let oc = ray.origin - self.center;
+        let center = self.center_at(ray.time);
+        let oc = ray.origin - center;
         let a = ray.direction.squared_length();
         let b = 2.0 * oc.dot(&ray.direction);
         let c = oc.squared_length() - self.radius * self.radius;
@@ -63,7 +100,7 @@ impl Hit for Sphere {
             for distance in distances {
                 if (distance < t_max) && (distance > t_min) {
                     let point = ray.get_point(distance);
-                    let norm = (point - self.center).get_unit();
+                    let norm = (point - center).get_unit();
                     return Some(HitRes::new(point, distance, norm, self.material));
                 }
             }
@@ -74,15 +111,24 @@ impl Hit for Sphere {
 
 pub struct Surfaces {
     surfaces: Vec<Box<dyn Hit + Sync>>,
+    environment: Environment,
 }
 
 impl Surfaces {
     pub fn new(surfaces: Vec<Box<dyn Hit + Sync>>) -> Self {
-        Self { surfaces }
+        Self {
+            surfaces,
+            environment: Environment::Gradient,
+        }
     }
 
     pub fn generate() -> Self {
-        let mut rng = rand::thread_rng();
+        Self::generate_with_seed(rand::random())
+    }
+
+    /// Same random scene as `generate`, reproducible for a given `seed`.
+    pub fn generate_with_seed(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
         let mut rnd = || rng.gen::<f32>();
 
         let mut surfaces: Vec<Box<dyn Hit + Sync>> = vec![];
@@ -155,13 +201,26 @@ impl Surfaces {
             },
         )));
 
-        Self { surfaces }
+        Self::new(surfaces)
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.environment = environment;
     }
 
     pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRes> {
         self.surfaces
             .iter()
-            .filter_map(|sphere| sphere.hit(&ray, t_min, t_max))
+            .enumerate()
+            .filter_map(|(object_id, surface)| {
+                let mut hit_res = surface.hit(ray, t_min, t_max)?;
+                hit_res.object_id = object_id;
+                Some(hit_res)
+            })
             .min_by_key(|hit_res| (hit_res.distance * 100000.0) as i32)
     }
 }
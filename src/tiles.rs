@@ -0,0 +1,137 @@
+use std::thread;
+
+use crossbeam::{queue::SegQueue, scope};
+
+/// Rectangular block of an image, holding mutable row slices of its items.
+pub struct Tile<'a, T> {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub rows: Vec<&'a mut [T]>,
+}
+
+impl<'a, T> Tile<'a, T> {
+    /// Iterates over the tile items together with their image coordinates.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, usize, &mut T)> + use<'_, 'a, T> {
+        let (x0, y0) = (self.x, self.y);
+        self.rows.iter_mut().enumerate().flat_map(move |(dy, row)| {
+            row.iter_mut()
+                .enumerate()
+                .map(move |(dx, item)| (x0 + dx, y0 + dy, item))
+        })
+    }
+}
+
+/// Splits row-major `items` of an image with the given `width` into square
+/// tiles of `tile_size` (smaller at the right and bottom edges).
+pub fn split_tiles<T>(items: &mut [T], width: usize, tile_size: usize) -> Vec<Tile<'_, T>> {
+    let tile_size = tile_size.max(1);
+    let n_cols = width.div_ceil(tile_size);
+    let mut tiles: Vec<Tile<T>> = vec![];
+
+    for (y, row) in items.chunks_mut(width).enumerate() {
+        let ty = y / tile_size;
+        for (tx, segment) in row.chunks_mut(tile_size).enumerate() {
+            let idx = ty * n_cols + tx;
+            if idx == tiles.len() {
+                tiles.push(Tile {
+                    x: tx * tile_size,
+                    y,
+                    width: segment.len(),
+                    height: 0,
+                    rows: vec![],
+                });
+            }
+            tiles[idx].height += 1;
+            tiles[idx].rows.push(segment);
+        }
+    }
+    tiles
+}
+
+/// Number of tiles `split_tiles` produces for an image of the given size.
+pub fn count_tiles(width: usize, height: usize, tile_size: usize) -> usize {
+    let tile_size = tile_size.max(1);
+    width.div_ceil(tile_size) * height.div_ceil(tile_size)
+}
+
+/// Number of worker threads to use: `n_threads` if given, otherwise the
+/// available parallelism of the machine.
+pub fn get_n_threads(n_threads: Option<usize>) -> usize {
+    n_threads
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+        .max(1)
+}
+
+/// Processes `tiles` with `n_threads` workers, each of which keeps pulling
+/// the next tile from a shared queue until it is empty.
+pub fn for_each_tile<'a, T, F>(tiles: Vec<Tile<'a, T>>, n_threads: usize, f: F)
+where
+    T: Send,
+    F: Fn(Tile<'a, T>) + Sync,
+{
+    let n_workers = n_threads.min(tiles.len());
+    let queue = SegQueue::new();
+    for tile in tiles {
+        queue.push(tile);
+    }
+
+    scope(|s| {
+        for _ in 0..n_workers {
+            s.spawn(|_| {
+                while let Some(tile) = queue.pop() {
+                    f(tile);
+                }
+            });
+        }
+    })
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_tiles() {
+        let (width, height) = (5, 3);
+        let mut items: Vec<usize> = (0..width * height).collect();
+        let tiles = split_tiles(&mut items, width, 2);
+        assert_eq!(tiles.len(), count_tiles(width, height, 2));
+
+        let shapes: Vec<_> = tiles
+            .iter()
+            .map(|t| (t.x, t.y, t.width, t.height))
+            .collect();
+        assert_eq!(
+            shapes,
+            vec![
+                (0, 0, 2, 2),
+                (2, 0, 2, 2),
+                (4, 0, 1, 2),
+                (0, 2, 2, 1),
+                (2, 2, 2, 1),
+                (4, 2, 1, 1)
+            ]
+        );
+
+        for mut tile in tiles {
+            for (x, y, item) in tile.iter_mut() {
+                assert_eq!(*item, y * width + x);
+            }
+        }
+    }
+
+    #[test]
+    fn test_for_each_tile() {
+        let mut items = vec![0; 37 * 11];
+        let tiles = split_tiles(&mut items, 37, 4);
+        for_each_tile(tiles, 4, |mut tile| {
+            for (_, _, item) in tile.iter_mut() {
+                *item += 1;
+            }
+        });
+        assert!(items.iter().all(|&item| item == 1));
+    }
+}
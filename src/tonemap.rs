@@ -0,0 +1,189 @@
+use crate::vec3::Vec3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ToneMapper {
+    /// Clips values above one.
+    Clamp,
+    /// `x / (1 + x)` per channel.
+    Reinhard,
+    /// John Hable's Uncharted 2 filmic curve.
+    Hable,
+    /// Stephen Hill's fit of the ACES reference and output transforms.
+    Aces,
+}
+
+/// Output pipeline turning linear radiance into display sRGB values:
+/// exposure, white balance, tone mapping and the sRGB transfer function.
+#[derive(Clone, Copy, Debug)]
+pub struct ToneMapping {
+    /// Exposure compensation in stops.
+    pub exposure: f32,
+    /// Per-channel gains, see `white_balance_gains`.
+    pub white_balance: Vec3,
+    pub tone_mapper: ToneMapper,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            white_balance: Vec3::ones(),
+            tone_mapper: ToneMapper::Clamp,
+        }
+    }
+}
+
+impl ToneMapping {
+    /// Maps linear `radiance` to sRGB encoded values in [0, 1].
+    pub fn apply(&self, radiance: Vec3) -> Vec3 {
+        let color = radiance.scale(2f32.powf(self.exposure)) * self.white_balance;
+        let color = Vec3::new(color.x().max(0.0), color.y().max(0.0), color.z().max(0.0));
+        let color = match self.tone_mapper {
+            ToneMapper::Clamp => color,
+            ToneMapper::Reinhard => map_channels(color, |x| x / (1.0 + x)),
+            ToneMapper::Hable => {
+                let white_scale = 1.0 / hable(HABLE_WHITE);
+                map_channels(color, |x| hable(HABLE_EXPOSURE_BIAS * x) * white_scale)
+            }
+            ToneMapper::Aces => aces_fitted(color),
+        };
+        map_channels(color, |x| srgb_encode(x.clamp(0.0, 1.0)))
+    }
+}
+
+/// Gains that neutralize the color cast of a light with the given color
+/// temperature in Kelvin, normalized to keep the green channel.
+pub fn white_balance_gains(temperature: f32) -> Vec3 {
+    let color = blackbody_color(temperature);
+    Vec3::new(color.y() / color.x(), 1.0, color.y() / color.z())
+}
+
+/// sRGB transfer function (linear to encoded).
+pub fn srgb_encode(x: f32) -> f32 {
+    if x <= 0.003_130_8 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Inverse sRGB transfer function (encoded to linear).
+pub fn srgb_decode(x: f32) -> f32 {
+    if x <= 0.040_45 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+const HABLE_EXPOSURE_BIAS: f32 = 2.0;
+const HABLE_WHITE: f32 = 11.2;
+
+fn hable(x: f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+fn aces_fitted(color: Vec3) -> Vec3 {
+    let input = [
+        [0.597_19, 0.354_58, 0.048_23],
+        [0.076_00, 0.908_34, 0.015_66],
+        [0.028_40, 0.133_83, 0.837_77],
+    ];
+    let output = [
+        [1.604_75, -0.531_08, -0.073_67],
+        [-0.102_08, 1.108_13, -0.006_05],
+        [-0.003_27, -0.072_76, 1.076_02],
+    ];
+    let color = mul_matrix(&input, color);
+    let color = map_channels(color, |v| {
+        (v * (v + 0.024_578_6) - 0.000_090_537) / (v * (0.983_729 * v + 0.432_951) + 0.238_081)
+    });
+    mul_matrix(&output, color)
+}
+
+/// Approximate sRGB color of a black body (Tanner Helland's fit).
+fn blackbody_color(temperature: f32) -> Vec3 {
+    let t = temperature.clamp(1000.0, 40000.0) / 100.0;
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_73 * (t - 60.0).powf(-0.133_204_76)
+    };
+    let g = if t <= 66.0 {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122_16 * (t - 60.0).powf(-0.075_514_85)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_73 * (t - 10.0).ln() - 305.044_8
+    };
+    map_channels(Vec3::new(r, g, b), |x| {
+        srgb_decode((x / 255.0).clamp(0.01, 1.0))
+    })
+}
+
+fn mul_matrix(m: &[[f32; 3]; 3], v: Vec3) -> Vec3 {
+    let row = |r: &[f32; 3]| r[0] * v.x() + r[1] * v.y() + r[2] * v.z();
+    Vec3::new(row(&m[0]), row(&m[1]), row(&m[2]))
+}
+
+fn map_channels<F: Fn(f32) -> f32>(color: Vec3, f: F) -> Vec3 {
+    Vec3::new(f(color.x()), f(color.y()), f(color.z()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_srgb() {
+        assert_eq!(srgb_encode(0.0), 0.0);
+        assert!((srgb_encode(1.0) - 1.0).abs() < 1.0e-6);
+        assert!((srgb_encode(0.214_041) - 0.5).abs() < 1.0e-4);
+        for x in [0.001, 0.02, 0.3, 0.9] {
+            assert!((srgb_decode(srgb_encode(x)) - x).abs() < 1.0e-5);
+        }
+    }
+
+    #[test]
+    fn test_tone_mappers() {
+        for tone_mapper in [
+            ToneMapper::Clamp,
+            ToneMapper::Reinhard,
+            ToneMapper::Hable,
+            ToneMapper::Aces,
+        ] {
+            let tone_mapping = ToneMapping {
+                tone_mapper,
+                ..Default::default()
+            };
+            let mut prev = -1.0;
+            for x in [0.0, 0.01, 0.1, 0.5, 1.0, 4.0, 100.0] {
+                let y = tone_mapping.apply(Vec3::new(x, x, x)).x();
+                assert!((0.0..=1.0).contains(&y) && y >= prev, "{:?}", tone_mapper);
+                prev = y;
+            }
+            assert!(tone_mapping.apply(Vec3::zeros()).x() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_exposure_and_white_balance() {
+        let tone_mapping = ToneMapping {
+            exposure: 1.0,
+            ..Default::default()
+        };
+        let y = tone_mapping.apply(Vec3::new(0.25, 0.25, 0.25)).x();
+        assert!((y - srgb_encode(0.5)).abs() < 1.0e-6);
+
+        let gains = white_balance_gains(6600.0);
+        assert!((gains.x() - 1.0).abs() < 0.05 && (gains.z() - 1.0).abs() < 0.05);
+        let gains = white_balance_gains(3000.0);
+        assert!(gains.x() < 1.0 && gains.z() > 1.0);
+    }
+}
@@ -32,14 +32,15 @@ impl Vec3 {
     pub fn z(&self) -> f32 {
         self.data[2]
     }
+    /// 8-bit color components, clamped to [0, 255].
     pub fn r(&self) -> i32 {
-        self.data[0] as i32
+        self.data[0].clamp(0.0, 255.0) as i32
     }
     pub fn g(&self) -> i32 {
-        self.data[1] as i32
+        self.data[1].clamp(0.0, 255.0) as i32
     }
     pub fn b(&self) -> i32 {
-        self.data[2] as i32
+        self.data[2].clamp(0.0, 255.0) as i32
     }
 
     pub fn scale(&self, k: f32) -> Self {
@@ -169,6 +170,12 @@ impl PartialEq for Vec3 {
     }
 }
 
+/// Asserts that `a` and `b` are within `tolerance` of each other.
+#[cfg(test)]
+pub(crate) fn assert_close(a: Vec3, b: Vec3, tolerance: f32) {
+    assert!((a - b).length() < tolerance, "{:?} != {:?}", a, b);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -186,6 +193,9 @@ mod tests {
         assert_eq!(vec.y(), 2.0);
         assert_eq!(vec.z(), 3.0);
         assert_fields(&vec);
+
+        let vec = Vec3::new(-1.0, 300.0, 254.9);
+        assert_eq!((vec.r(), vec.g(), vec.b()), (0, 255, 254));
     }
 
     #[test]
@@ -197,7 +207,7 @@ mod tests {
         assert_eq!(vec_1.z(), 3.0);
 
         let vec_2 = Vec3::new(1.0, 2.0, 3.0);
-        vec_1 += vec_2.clone();
+        vec_1 += vec_2;
         assert_eq!(vec_1.x(), 2.0);
         assert_eq!(vec_1.y(), 4.0);
         assert_eq!(vec_1.z(), 6.0);
@@ -213,17 +223,17 @@ mod tests {
         assert_eq!(vec_3.z(), 3.0);
         vec_3 += vec_2;
 
-        vec_1 -= vec_2.clone();
+        vec_1 -= vec_2;
         assert_eq!(vec_1.x(), 1.0);
         assert_eq!(vec_1.y(), 2.0);
         assert_eq!(vec_1.z(), 3.0);
 
-        vec_1 *= vec_2.clone();
+        vec_1 *= vec_2;
         assert_eq!(vec_1.x(), 1.0);
         assert_eq!(vec_1.y(), 4.0);
         assert_eq!(vec_1.z(), 9.0);
 
-        vec_1 /= vec_2.clone();
+        vec_1 /= vec_2;
         assert_eq!(vec_1.x(), 1.0);
         assert_eq!(vec_1.y(), 2.0);
         assert_eq!(vec_1.z(), 3.0);
@@ -0,0 +1,387 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{
+    buffer::{get_extension, unsupported_format},
+    ppm::quantize,
+    vec3::Vec3,
+};
+
+/// Movie file taking display colors in [0, 1] frame by frame, rows top to
+/// bottom, e.g. `Image::colors`.
+pub trait VideoWriter {
+    fn write_frame(&mut self, colors: &[Vec3]) -> io::Result<()>;
+
+    /// Completes the file; frames written afterwards are lost.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// Creates a video file in the format given by the extension: `y4m` or
+/// uncompressed `avi`.
+pub fn create_video<P: AsRef<Path>>(
+    file_path: P,
+    width: usize,
+    height: usize,
+    fps: f32,
+) -> io::Result<Box<dyn VideoWriter>> {
+    let file_path = file_path.as_ref();
+    match get_extension(file_path).as_deref() {
+        Some("y4m") => {
+            let out = BufWriter::new(File::create(file_path)?);
+            Ok(Box::new(Y4mWriter::new(out, width, height, fps)?))
+        }
+        Some("avi") => {
+            let out = BufWriter::new(File::create(file_path)?);
+            Ok(Box::new(AviWriter::new(out, width, height, fps)?))
+        }
+        _ => Err(unsupported_format(file_path)),
+    }
+}
+
+/// Frame rate as a reduced fraction of thousandths, so `fps` must be at
+/// least 0.001.
+fn frame_rate(fps: f32) -> (u32, u32) {
+    if fps.is_nan() || fps < 0.001 {
+        panic!("fps must be at least 0.001!");
+    }
+    let (mut num, mut den) = ((fps * 1000.0).round() as u32, 1000);
+    let (mut a, mut b) = (num, den);
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    num /= a;
+    den /= a;
+    (num, den)
+}
+
+fn check_frame(width: usize, height: usize, colors: &[Vec3]) -> io::Result<()> {
+    if colors.len() != width * height {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "frame size doesn't match the video",
+        ));
+    }
+    Ok(())
+}
+
+/// YUV4MPEG2 stream with 4:2:0 chroma subsampling in limited range BT.709,
+/// readable by most video tools (e.g. `ffmpeg -i movie.y4m movie.mp4`).
+pub struct Y4mWriter<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(mut out: W, width: usize, height: usize, fps: f32) -> io::Result<Self> {
+        let (num, den) = frame_rate(fps);
+        writeln!(
+            out,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C420jpeg XCOLORRANGE=LIMITED",
+            width, height, num, den
+        )?;
+        Ok(Self { out, width, height })
+    }
+}
+
+impl<W: Write> VideoWriter for Y4mWriter<W> {
+    fn write_frame(&mut self, colors: &[Vec3]) -> io::Result<()> {
+        check_frame(self.width, self.height, colors)?;
+        let (width, height) = (self.width, self.height);
+        let yuv: Vec<_> = colors.iter().map(rgb_to_yuv).collect();
+
+        self.out.write_all(b"FRAME\n")?;
+        let luma: Vec<u8> = yuv
+            .iter()
+            .map(|&(y, _, _)| (16.0 + 219.0 * y).round() as u8)
+            .collect();
+        self.out.write_all(&luma)?;
+
+        // Average the chroma of 2x2 blocks, cut at the right and bottom
+        // edges of odd sizes.
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+        let mut u_plane = Vec::with_capacity(chroma_width * chroma_height);
+        let mut v_plane = Vec::with_capacity(chroma_width * chroma_height);
+        for cy in 0..chroma_height {
+            for cx in 0..chroma_width {
+                let (mut u_sum, mut v_sum, mut n) = (0.0, 0.0, 0.0);
+                for y in 2 * cy..(2 * cy + 2).min(height) {
+                    for x in 2 * cx..(2 * cx + 2).min(width) {
+                        let (_, u, v) = yuv[y * width + x];
+                        u_sum += u;
+                        v_sum += v;
+                        n += 1.0;
+                    }
+                }
+                u_plane.push((128.0 + 224.0 * u_sum / n).round() as u8);
+                v_plane.push((128.0 + 224.0 * v_sum / n).round() as u8);
+            }
+        }
+        self.out.write_all(&u_plane)?;
+        self.out.write_all(&v_plane)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// BT.709 luma in [0, 1] and chroma in [-0.5, 0.5] of a display color.
+fn rgb_to_yuv(color: &Vec3) -> (f32, f32, f32) {
+    let [r, g, b] = [0, 1, 2].map(|i| color[i].clamp(0.0, 1.0));
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let u = (b - y) / 1.8556;
+    let v = (r - y) / 1.5748;
+    (y, u, v)
+}
+
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+/// Uncompressed 24-bit RGB AVI. Frames are streamed to `out` and the
+/// headers completed by `finish`; the RIFF format limits the file to 4 GB.
+pub struct AviWriter<W: Write + Seek> {
+    out: W,
+    width: usize,
+    height: usize,
+    /// Offset of the `movi` list fourcc, which index offsets are relative to.
+    movi_offset: u64,
+    /// Offsets and sizes of the frame chunks relative to `movi_offset`.
+    index: Vec<(u32, u32)>,
+    finished: bool,
+}
+
+/// Offsets of the fields patched by `AviWriter::finish`.
+const RIFF_SIZE_OFFSET: u64 = 4;
+const AVIH_TOTAL_FRAMES_OFFSET: u64 = 48;
+const STRH_LENGTH_OFFSET: u64 = 140;
+
+impl<W: Write + Seek> AviWriter<W> {
+    pub fn new(mut out: W, width: usize, height: usize, fps: f32) -> io::Result<Self> {
+        let (num, den) = frame_rate(fps);
+        let frame_size = Self::frame_size(width, height);
+        let (width_u32, height_u32) = (width as u32, height as u32);
+
+        let mut header = vec![];
+        header.extend(b"RIFF");
+        header.extend(0u32.to_le_bytes());
+        header.extend(b"AVI ");
+        header.extend(b"LIST");
+        header.extend((4 + 8 + 56 + 8 + 4 + 8 + 56 + 8 + 40u32).to_le_bytes());
+        header.extend(b"hdrl");
+
+        header.extend(b"avih");
+        header.extend(56u32.to_le_bytes());
+        let micro_sec_per_frame = (1e6 * den as f64 / num as f64).round() as u32;
+        let bytes_per_sec = (frame_size as f64 * num as f64 / den as f64) as u32;
+        for value in [
+            micro_sec_per_frame,
+            bytes_per_sec,
+            0,
+            AVIF_HASINDEX,
+            0, // total frames
+            0,
+            1,
+            frame_size,
+            width_u32,
+            height_u32,
+            0,
+            0,
+            0,
+            0,
+        ] {
+            header.extend(value.to_le_bytes());
+        }
+
+        header.extend(b"LIST");
+        header.extend((4 + 8 + 56 + 8 + 40u32).to_le_bytes());
+        header.extend(b"strl");
+        header.extend(b"strh");
+        header.extend(56u32.to_le_bytes());
+        header.extend(b"vids");
+        header.extend(b"DIB ");
+        header.extend(0u32.to_le_bytes()); // flags
+        header.extend(0u16.to_le_bytes()); // priority
+        header.extend(0u16.to_le_bytes()); // language
+        for value in [0, den, num, 0, 0, frame_size, u32::MAX, 0] {
+            header.extend(value.to_le_bytes());
+        }
+        for value in [0, 0, width as u16, height as u16] {
+            header.extend(value.to_le_bytes());
+        }
+
+        header.extend(b"strf");
+        header.extend(40u32.to_le_bytes());
+        header.extend(40u32.to_le_bytes());
+        header.extend(width_u32.to_le_bytes());
+        header.extend(height_u32.to_le_bytes()); // positive: bottom-up rows
+        header.extend(1u16.to_le_bytes());
+        header.extend(24u16.to_le_bytes());
+        for value in [0, frame_size, 0, 0, 0, 0] {
+            header.extend(value.to_le_bytes());
+        }
+
+        header.extend(b"LIST");
+        header.extend(0u32.to_le_bytes());
+        let movi_offset = header.len() as u64;
+        header.extend(b"movi");
+        out.write_all(&header)?;
+
+        Ok(Self {
+            out,
+            width,
+            height,
+            movi_offset,
+            index: vec![],
+            finished: false,
+        })
+    }
+
+    /// Bytes of a frame, rows padded to 4 bytes.
+    fn frame_size(width: usize, height: usize) -> u32 {
+        ((3 * width).next_multiple_of(4) * height) as u32
+    }
+
+    fn too_large() -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, "AVI file exceeds 4 GB")
+    }
+}
+
+impl<W: Write + Seek> VideoWriter for AviWriter<W> {
+    fn write_frame(&mut self, colors: &[Vec3]) -> io::Result<()> {
+        check_frame(self.width, self.height, colors)?;
+        let frame_size = Self::frame_size(self.width, self.height);
+        let offset = match self.index.last() {
+            Some(&(offset, size)) => offset as u64 + 8 + size as u64,
+            None => 4,
+        };
+        if self.movi_offset + offset + 8 + frame_size as u64 > u32::MAX as u64 {
+            return Err(Self::too_large());
+        }
+
+        let mut chunk = Vec::with_capacity(8 + frame_size as usize);
+        chunk.extend(b"00db");
+        chunk.extend(frame_size.to_le_bytes());
+        let row_size = (3 * self.width).next_multiple_of(4);
+        for row in colors.chunks(self.width.max(1)).rev() {
+            for color in row {
+                let [r, g, b] = quantize(color);
+                chunk.extend([b, g, r]);
+            }
+            chunk.resize(chunk.len() + row_size - 3 * self.width, 0);
+        }
+        self.out.write_all(&chunk)?;
+        self.index.push((offset as u32, frame_size));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        let movi_size = match self.index.last() {
+            Some(&(offset, size)) => offset + 8 + size,
+            None => 4,
+        };
+        let mut idx1 = vec![];
+        idx1.extend(b"idx1");
+        idx1.extend((16 * self.index.len() as u32).to_le_bytes());
+        for &(offset, size) in self.index.iter() {
+            idx1.extend(b"00db");
+            idx1.extend(AVIIF_KEYFRAME.to_le_bytes());
+            idx1.extend(offset.to_le_bytes());
+            idx1.extend(size.to_le_bytes());
+        }
+        self.out.write_all(&idx1)?;
+
+        let file_size = self.movi_offset + movi_size as u64 + idx1.len() as u64;
+        if file_size > u32::MAX as u64 {
+            return Err(Self::too_large());
+        }
+        let n_frames = self.index.len() as u32;
+        for (offset, value) in [
+            (RIFF_SIZE_OFFSET, file_size as u32 - 8),
+            (AVIH_TOTAL_FRAMES_OFFSET, n_frames),
+            (STRH_LENGTH_OFFSET, n_frames),
+            (self.movi_offset - 4, movi_size),
+        ] {
+            self.out.seek(SeekFrom::Start(offset))?;
+            self.out.write_all(&value.to_le_bytes())?;
+        }
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_frame_rate() {
+        assert_eq!(frame_rate(24.0), (24, 1));
+        assert_eq!(frame_rate(0.001), (1, 1000));
+        assert_eq!(frame_rate(29.97), (2997, 100));
+    }
+
+    #[test]
+    fn test_y4m() {
+        let (width, height) = (3, 3);
+        let mut colors = vec![Vec3::ones(); 9];
+        colors[0] = Vec3::new(1.0, 0.0, 0.0);
+        let mut writer = Y4mWriter::new(vec![], width, height, 25.0).unwrap();
+        writer.write_frame(&colors).unwrap();
+        writer.write_frame(&colors).unwrap();
+        writer.finish().unwrap();
+
+        let data = writer.out;
+        let header = b"YUV4MPEG2 W3 H3 F25:1 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED\n";
+        assert!(data.starts_with(header));
+        let frame = &data[header.len()..];
+        let frame_size = 6 + 9 + 2 * 4;
+        assert_eq!(frame.len(), 2 * frame_size);
+        assert!(frame.starts_with(b"FRAME\n"));
+        let planes = &frame[6..frame_size];
+        // Red and white luma, neutral chroma away from the red pixel.
+        assert_eq!(planes[0], 63);
+        assert_eq!(planes[1], 235);
+        assert!(planes[9] < 128 && planes[13] > 128);
+        assert_eq!((planes[10], planes[14]), (128, 128));
+    }
+
+    #[test]
+    fn test_avi() {
+        let (width, height) = (3, 2);
+        let mut colors = vec![Vec3::zeros(); 6];
+        colors[0] = Vec3::new(1.0, 0.0, 0.0);
+        let mut writer = AviWriter::new(Cursor::new(vec![]), width, height, 24.0).unwrap();
+        for _ in 0..3 {
+            writer.write_frame(&colors).unwrap();
+        }
+        writer.finish().unwrap();
+        let data = writer.out.into_inner();
+
+        let read_u32 =
+            |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(read_u32(4) as usize, data.len() - 8);
+        assert_eq!(&data[8..12], b"AVI ");
+        assert_eq!(read_u32(AVIH_TOTAL_FRAMES_OFFSET as usize), 3);
+        assert_eq!(read_u32(STRH_LENGTH_OFFSET as usize), 3);
+        assert_eq!(&data[STRH_LENGTH_OFFSET as usize - 32..][..4], b"vids");
+
+        // 12 bytes per padded row, the top left red pixel is in the last row.
+        let movi = writer.movi_offset as usize;
+        assert_eq!(&data[movi..movi + 4], b"movi");
+        assert_eq!(read_u32(movi - 4) as usize, 4 + 3 * (8 + 24));
+        let frame = &data[movi + 12..movi + 12 + 24];
+        assert_eq!(&frame[12..15], &[0, 0, 255]);
+        assert_eq!(&data[data.len() - 16 * 3 - 8..][..4], b"idx1");
+    }
+}